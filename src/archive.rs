@@ -0,0 +1,339 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+};
+
+use bzip2::read::BzDecoder;
+use flate2::read::GzDecoder;
+use log::info;
+
+use crate::error::AppError;
+
+/// 解压限制, 防止压缩炸弹
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub max_entries: usize,
+    /// 解压后的总大小
+    pub max_size: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_entries: 10_000,
+            max_size: 512 * 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Format {
+    Zip,
+    TarGz,
+    TarBz2,
+}
+
+/// 按文件头判断格式
+fn format(path: &Path) -> Result<Format, AppError> {
+    let mut magic = [0u8; 4];
+    let n = File::open(path)?.read(&mut magic)?;
+
+    match &magic[..n] {
+        [0x50, 0x4b, 0x03, 0x04] | [0x50, 0x4b, 0x05, 0x06] => Ok(Format::Zip),
+        [0x1f, 0x8b, ..] => Ok(Format::TarGz),
+        [0x42, 0x5a, 0x68, ..] => Ok(Format::TarBz2),
+        _ => Err(AppError::Validation(format!(
+            "{} is not zip/tar.gz/tar.bz2",
+            path.display()
+        ))),
+    }
+}
+
+fn illegal(name: &str, msg: &str) -> AppError {
+    AppError::Validation(format!("illegal archive entry {} : {}", name, msg))
+}
+
+/// 压缩包中的相对路径, 不允许绝对路径和 `..`
+fn entry_path(name: &str) -> Result<PathBuf, AppError> {
+    let mut path = PathBuf::new();
+
+    for c in Path::new(name).components() {
+        match c {
+            Component::Normal(p) => path.push(p),
+            Component::CurDir => {}
+            _ => return Err(illegal(name, "path traversal")),
+        }
+    }
+
+    Ok(path)
+}
+
+/// 目标路径, 已存在的上级目录或文件不能是链接
+fn target(dir: &Path, relative: &Path) -> Result<PathBuf, AppError> {
+    let mut path = dir.to_path_buf();
+
+    for c in relative.components() {
+        path.push(c);
+        if let Ok(meta) = fs::symlink_metadata(&path) {
+            if meta.file_type().is_symlink() {
+                return Err(illegal(&relative.to_string_lossy(), "target is a symlink"));
+            }
+        }
+    }
+
+    Ok(path)
+}
+
+struct Extractor<'a> {
+    dir: &'a Path,
+    limits: &'a Limits,
+    entries: usize,
+    size: u64,
+}
+
+impl<'a> Extractor<'a> {
+    fn entry(&mut self, name: &str) -> Result<Option<PathBuf>, AppError> {
+        self.entries += 1;
+        if self.entries > self.limits.max_entries {
+            return Err(AppError::Validation(format!(
+                "archive has more than {} entries",
+                self.limits.max_entries
+            )));
+        }
+
+        let relative = entry_path(name)?;
+        if relative.as_os_str().is_empty() {
+            return Ok(None);
+        }
+
+        target(self.dir, &relative).map(Some)
+    }
+
+    fn dir(&mut self, name: &str) -> Result<(), AppError> {
+        if let Some(path) = self.entry(name)? {
+            fs::create_dir_all(path)?;
+        }
+
+        Ok(())
+    }
+
+    /// 按实际读取的大小计算限制, 不信任压缩包中记录的大小
+    fn file<R: Read>(&mut self, name: &str, reader: R) -> Result<(), AppError> {
+        let path = match self.entry(name)? {
+            Some(p) => p,
+            None => return Ok(()),
+        };
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let remain = self.limits.max_size - self.size;
+        let mut out = File::create(&path)?;
+        let n = io::copy(&mut reader.take(remain + 1), &mut out)?;
+        if n > remain {
+            return Err(AppError::Validation(format!(
+                "archive larger than {} bytes",
+                self.limits.max_size
+            )));
+        }
+        self.size += n;
+
+        Ok(())
+    }
+}
+
+fn extract_zip(archive: &Path, ex: &mut Extractor) -> Result<(), AppError> {
+    let mut zip = zip::ZipArchive::new(File::open(archive)?)
+        .map_err(|e| AppError::Validation(format!("open zip error : {}", e)))?;
+
+    for i in 0..zip.len() {
+        let file = zip
+            .by_index(i)
+            .map_err(|e| AppError::Validation(format!("read zip error : {}", e)))?;
+        let name = file.name().to_string();
+
+        // S_IFLNK
+        if file.unix_mode().map(|m| m & 0o170000 == 0o120000) == Some(true) {
+            return Err(illegal(&name, "symlink not allowed"));
+        }
+
+        if file.is_dir() {
+            ex.dir(&name)?;
+        } else {
+            ex.file(&name, file)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn extract_tar<R: Read>(reader: R, ex: &mut Extractor) -> Result<(), AppError> {
+    let mut tar = tar::Archive::new(reader);
+
+    for entry in tar.entries()? {
+        let entry = entry?;
+        let name = entry.path()?.to_string_lossy().to_string();
+        let kind = entry.header().entry_type();
+
+        if kind.is_symlink() || kind.is_hard_link() {
+            return Err(illegal(&name, "link not allowed"));
+        } else if kind.is_dir() {
+            ex.dir(&name)?;
+        } else if kind.is_file() || kind.is_contiguous() {
+            ex.file(&name, entry)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// 解压 zip/tar.gz/tar.bz2 到 dir, 保留目录结构, 返回解压的文件大小
+pub fn extract(archive: &Path, dir: &Path, limits: &Limits) -> Result<u64, AppError> {
+    let format = format(archive)?;
+    info!(
+        "extract {:?} {} to {} ...",
+        format,
+        archive.display(),
+        dir.display()
+    );
+
+    fs::create_dir_all(dir)?;
+
+    let mut ex = Extractor {
+        dir,
+        limits,
+        entries: 0,
+        size: 0,
+    };
+
+    match format {
+        Format::Zip => extract_zip(archive, &mut ex)?,
+        Format::TarGz => extract_tar(GzDecoder::new(File::open(archive)?), &mut ex)?,
+        Format::TarBz2 => extract_tar(BzDecoder::new(File::open(archive)?), &mut ex)?,
+    }
+
+    Ok(ex.size)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{self, File},
+        io::Write,
+        path::{Path, PathBuf},
+    };
+
+    use flate2::{write::GzEncoder, Compression};
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::{entry_path, extract, Limits};
+
+    fn temp_dir() -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("archive_{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn zip(path: &Path, files: &[(&str, &str)]) {
+        let mut zip = ZipWriter::new(File::create(path).unwrap());
+        zip.add_directory("config/", FileOptions::default())
+            .unwrap();
+        for (name, content) in files {
+            zip.start_file(*name, FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    /// 直接写 header, tar::Builder 会拒绝 `..`
+    fn tar_gz(path: &Path, name: &str, link: Option<&str>) {
+        let mut tar = tar::Builder::new(GzEncoder::new(
+            File::create(path).unwrap(),
+            Compression::default(),
+        ));
+
+        let mut header = tar::Header::new_old();
+        header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+        header.set_mode(0o644);
+        match link {
+            Some(l) => {
+                header.set_entry_type(tar::EntryType::Symlink);
+                header.set_link_name(l).unwrap();
+                header.set_size(0);
+            }
+            None => header.set_size(4),
+        }
+        header.set_cksum();
+
+        let data: &[u8] = if link.is_some() { b"" } else { b"test" };
+        tar.append(&header, data).unwrap();
+        tar.into_inner().unwrap().finish().unwrap();
+    }
+
+    #[test]
+    fn test_entry_path() {
+        assert_eq!(
+            entry_path("./config/a.json").unwrap(),
+            Path::new("config/a.json")
+        );
+        assert!(entry_path("../a.json").is_err());
+        assert!(entry_path("config/../../a.json").is_err());
+        assert!(entry_path("/etc/passwd").is_err());
+    }
+
+    #[test]
+    fn test_extract_zip() {
+        let dir = temp_dir();
+        let path = dir.join("config.zip");
+        zip(
+            &path,
+            &[("config/app.json", "{}"), ("config/img/logo.png", "png")],
+        );
+
+        let out = dir.join("out");
+        assert_eq!(extract(&path, &out, &Limits::default()).unwrap(), 5);
+        assert_eq!(
+            fs::read_to_string(out.join("config/img/logo.png")).unwrap(),
+            "png"
+        );
+
+        let limits = Limits {
+            max_entries: 10,
+            max_size: 4,
+        };
+        assert!(extract(&path, &dir.join("small"), &limits).is_err());
+
+        zip(&path, &[("../evil.txt", "evil")]);
+        assert!(extract(&path, &dir.join("evil"), &Limits::default()).is_err());
+        assert!(!dir.join("evil.txt").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_extract_tar() {
+        let dir = temp_dir();
+        let path = dir.join("config.tar.gz");
+
+        tar_gz(&path, "config/app.json", None);
+        assert_eq!(
+            extract(&path, &dir.join("out"), &Limits::default()).unwrap(),
+            4
+        );
+        assert!(dir.join("out/config/app.json").is_file());
+
+        tar_gz(&path, "../evil.txt", None);
+        assert!(extract(&path, &dir.join("evil"), &Limits::default()).is_err());
+        assert!(!dir.join("evil.txt").exists());
+
+        tar_gz(&path, "passwd", Some("/etc/passwd"));
+        assert!(extract(&path, &dir.join("link"), &Limits::default()).is_err());
+
+        fs::write(&path, "not an archive").unwrap();
+        assert!(extract(&path, &dir.join("bad"), &Limits::default()).is_err());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
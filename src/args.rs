@@ -54,4 +54,60 @@ pub struct Opt {
         help = "android sdk路径"
     )]
     pub android_home: String,
+
+    #[structopt(
+        long = "admin-token",
+        default_value = "",
+        help = "管理员token, 设置后打包管理服务开启接口鉴权"
+    )]
+    pub admin_token: String,
+
+    #[structopt(
+        long = "hook-secret",
+        default_value = "",
+        help = "gitlab/gitea webhook 密钥, 为空时不接收 webhook"
+    )]
+    pub hook_secret: String,
+
+    #[structopt(
+        long = "keystore-secret",
+        default_value = "",
+        help = "签名证书密码的加密密钥, 使用足够长的随机串, manager 和打包服务需要相同"
+    )]
+    pub keystore_secret: String,
+
+    #[structopt(
+        long = "worker-token",
+        default_value = "",
+        help = "manager 读取打包服务实时日志的密钥, manager 和打包服务需要相同"
+    )]
+    pub worker_token: String,
+
+    #[structopt(
+        long = "idempotency-window",
+        default_value = "86400",
+        help = "相同 Idempotency-Key 重复提交的有效时间, 单位秒"
+    )]
+    pub idempotency_window: i64,
+
+    #[structopt(
+        long = "rate-limit",
+        default_value = "0",
+        help = "每个 token 或 ip 在 rate-window 内最多提交的任务数, 0 为不限制"
+    )]
+    pub rate_limit: i64,
+
+    #[structopt(
+        long = "rate-window",
+        default_value = "60",
+        help = "限流时间窗口, 单位秒"
+    )]
+    pub rate_window: i64,
+
+    #[structopt(
+        long = "max-waiting",
+        default_value = "0",
+        help = "每个项目最多等待中的任务数, 0 为不限制"
+    )]
+    pub max_waiting: i64,
 }
@@ -0,0 +1,263 @@
+use std::{
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{build_params::Variant, error::AppError};
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum ArtifactKind {
+    Apk,
+    Aab,
+    Mapping,
+    /// zkm 混淆的 ChangeLog
+    Changelog,
+}
+
+impl ArtifactKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ArtifactKind::Apk => "apk",
+            ArtifactKind::Aab => "aab",
+            ArtifactKind::Mapping => "mapping",
+            ArtifactKind::Changelog => "changelog",
+        }
+    }
+}
+
+/// 编译产物
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Artifact {
+    pub kind: ArtifactKind,
+    /// 相对源码目录的路径
+    pub path: String,
+    /// 上传后的文件名
+    pub name: String,
+    pub size: u64,
+    pub sha256: String,
+    pub fid: String,
+}
+
+fn walk(dir: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            walk(&path, files)?;
+        } else {
+            files.push(path);
+        }
+    }
+
+    Ok(())
+}
+
+/// 查找 gradle outputs 目录下指定变体的产物
+pub fn discover(
+    outputs: &Path,
+    variant: &Variant,
+) -> Result<Vec<(ArtifactKind, PathBuf)>, AppError> {
+    let mut files = Vec::new();
+    walk(outputs, &mut files)?;
+    files.sort();
+
+    let apk_dir = variant.apk_dir() + "/";
+    let bundle_dir = format!("bundle/{}/", variant.name());
+    let mapping_dir = format!("mapping/{}/", variant.name());
+
+    let list = files
+        .into_iter()
+        .filter_map(|path| {
+            let relative = path
+                .strip_prefix(outputs)
+                .ok()?
+                .to_string_lossy()
+                .to_string();
+            let name = path.file_name()?.to_string_lossy().to_string();
+
+            let kind = if relative.starts_with(&apk_dir)
+                && name.ends_with(".apk")
+                && !name.ends_with("-unsigned.apk")
+            {
+                ArtifactKind::Apk
+            } else if relative.starts_with(&bundle_dir) && name.ends_with(".aab") {
+                ArtifactKind::Aab
+            } else if relative.starts_with(&mapping_dir) && name == "mapping.txt" {
+                ArtifactKind::Mapping
+            } else if relative.starts_with("zkm/") {
+                ArtifactKind::Changelog
+            } else {
+                return None;
+            };
+
+            Some((kind, path))
+        })
+        .collect();
+
+    Ok(list)
+}
+
+/// 没有签名的 apk
+pub fn unsigned_apks(outputs: &Path, variant: &Variant) -> Result<Vec<PathBuf>, AppError> {
+    let mut files = Vec::new();
+    walk(&outputs.join(variant.apk_dir()), &mut files)?;
+    files.sort();
+
+    Ok(files
+        .into_iter()
+        .filter(|p| p.to_string_lossy().ends_with("-unsigned.apk"))
+        .collect())
+}
+
+/// 多个 apk 时优先 universal, 作为默认下载的安装包
+pub fn primary_apk(list: &[Artifact]) -> Option<&Artifact> {
+    let apks = list
+        .iter()
+        .filter(|a| a.kind == ArtifactKind::Apk)
+        .collect::<Vec<&Artifact>>();
+
+    apks.iter()
+        .find(|a| a.path.contains("universal"))
+        .or(apks.first())
+        .copied()
+}
+
+/// 按类型或文件名查找
+pub fn find<'a>(list: &'a [Artifact], name: &str) -> Option<&'a Artifact> {
+    match list.iter().find(|a| a.name == name) {
+        Some(a) => Some(a),
+        None if name == ArtifactKind::Apk.as_str() => primary_apk(list),
+        None => list.iter().find(|a| a.kind.as_str() == name),
+    }
+}
+
+pub fn sha256(path: &Path) -> Result<String, AppError> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+
+    Ok(hex::encode(hasher.finalize()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{discover, find, sha256, unsigned_apks, Artifact, ArtifactKind};
+    use crate::build_params::Variant;
+
+    fn artifact(kind: ArtifactKind, path: &str, name: &str) -> Artifact {
+        Artifact {
+            kind,
+            path: path.to_string(),
+            name: name.to_string(),
+            size: 0,
+            sha256: String::new(),
+            fid: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_discover() {
+        let dir = std::env::temp_dir().join(format!("artifact_{}", uuid::Uuid::new_v4()));
+        let files = [
+            "apk/release/app-arm64-v8a-release.apk",
+            "apk/release/app-universal-release.apk",
+            "apk/release/app-release-unsigned.apk",
+            "apk/release/output-metadata.json",
+            "apk/debug/app-debug.apk",
+            "bundle/release/app-release.aab",
+            "mapping/release/mapping.txt",
+            "mapping/release/seeds.txt",
+            "mapping/debug/mapping.txt",
+            "zkm/ChangeLog.txt",
+        ];
+        let release = Variant {
+            flavors: Vec::new(),
+            build_type: "release".to_string(),
+        };
+        for f in files.iter() {
+            let path = dir.join(f);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, "test").unwrap();
+        }
+
+        let list = discover(&dir, &release).unwrap();
+        let kinds = list.iter().map(|(k, _)| *k).collect::<Vec<ArtifactKind>>();
+        assert_eq!(
+            kinds,
+            vec![
+                ArtifactKind::Apk,
+                ArtifactKind::Apk,
+                ArtifactKind::Aab,
+                ArtifactKind::Mapping,
+                ArtifactKind::Changelog
+            ]
+        );
+
+        assert_eq!(
+            sha256(&list[0].1).unwrap(),
+            "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+        );
+
+        let unsigned = unsigned_apks(&dir, &release).unwrap();
+        assert_eq!(unsigned.len(), 1);
+        assert!(unsigned[0].ends_with("app-release-unsigned.apk"));
+
+        let debug = Variant {
+            flavors: Vec::new(),
+            build_type: "debug".to_string(),
+        };
+        let list = discover(&dir, &debug).unwrap();
+        assert_eq!(list.len(), 3);
+        assert!(list[0].1.ends_with("apk/debug/app-debug.apk"));
+        assert!(list[1].1.ends_with("mapping/debug/mapping.txt"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_find() {
+        let list = vec![
+            artifact(
+                ArtifactKind::Apk,
+                "apk/release/app-arm64-v8a-release.apk",
+                "seed_arm64.apk",
+            ),
+            artifact(
+                ArtifactKind::Apk,
+                "apk/release/app-universal-release.apk",
+                "seed.apk",
+            ),
+            artifact(
+                ArtifactKind::Mapping,
+                "mapping/release/mapping.txt",
+                "seed_mapping.txt",
+            ),
+        ];
+
+        assert_eq!(find(&list, "apk").unwrap().name, "seed.apk");
+        assert_eq!(
+            find(&list, "seed_arm64.apk").unwrap().name,
+            "seed_arm64.apk"
+        );
+        assert_eq!(find(&list, "mapping").unwrap().name, "seed_mapping.txt");
+        assert!(find(&list, "aab").is_none());
+    }
+}
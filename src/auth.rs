@@ -0,0 +1,436 @@
+use std::{
+    cell::RefCell,
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll},
+};
+
+use actix_web::{
+    dev::{Service, ServiceRequest, ServiceResponse, Transform},
+    http::{HeaderMap, Method},
+    Error, HttpMessage, HttpRequest,
+};
+use bson::{doc, Bson, DateTime};
+use futures::future::{ok, Future, Ready};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::Config,
+    db::{Db, COLLECTION_TOKEN},
+    error::AppError,
+    utils::const_eq,
+};
+
+pub const ADMIN_NAME: &str = "admin";
+/// 使用 worker token 读取日志的客户端名称
+pub const WORKER_NAME: &str = "worker";
+
+/// 不需要鉴权的路径
+/// webhook 使用自己的密钥校验
+const PUBLIC_PATHS: [&str; 2] = ["/test/", "/hooks/"];
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    Submit,
+    Query,
+    Download,
+    Admin,
+}
+
+/// 数据库中只保存 token 的 sha256, 原始 token 只在创建时返回
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Token {
+    pub name: String,
+    pub token_hash: String,
+    /// token 的前 4 位, 列表中展示
+    pub prefix: String,
+    pub scopes: Vec<Scope>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expire: Option<DateTime>,
+    pub date: DateTime,
+}
+
+/// 鉴权通过后的客户端信息, 保存在 request extensions 中
+#[derive(Debug, Clone)]
+pub struct Client {
+    pub name: String,
+}
+
+impl Token {
+    /// 返回 token 和原始 token
+    pub fn new(name: &str, scopes: Vec<Scope>, expire: Option<DateTime>) -> (Self, String) {
+        let raw = uuid::Uuid::new_v4().to_simple().to_string();
+        let token = Token {
+            name: name.to_string(),
+            token_hash: Token::hash(&raw),
+            prefix: raw[..4].to_string(),
+            scopes,
+            expire,
+            date: DateTime(chrono::Utc::now()),
+        };
+
+        (token, raw)
+    }
+
+    pub fn hash(raw: &str) -> String {
+        hex::encode(Sha256::digest(raw.as_bytes()))
+    }
+
+    pub fn is_expired(&self) -> bool {
+        match self.expire {
+            Some(t) => t.0 < chrono::Utc::now(),
+            None => false,
+        }
+    }
+
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&Scope::Admin) || self.scopes.contains(&scope)
+    }
+
+    /// 列表展示时隐藏 token
+    pub fn masked(&self) -> Value {
+        let mut v = serde_json::to_value(self).unwrap();
+        if let Some(map) = v.as_object_mut() {
+            map.remove("token_hash");
+            map.remove("prefix");
+            map.insert(
+                "token".to_string(),
+                Value::from(self.prefix.clone() + "****"),
+            );
+        }
+        v
+    }
+
+    pub async fn find(token: &str) -> Result<Option<Token>, AppError> {
+        let result = Db::find_one(
+            COLLECTION_TOKEN,
+            doc! {"token_hash": Token::hash(token)},
+            None,
+        )
+        .await?;
+
+        match result {
+            Some(doc) => Ok(Some(bson::from_bson::<Token>(Bson::Document(doc))?)),
+            None => Ok(None),
+        }
+    }
+}
+
+/// 路径对应的权限
+pub fn required_scope(method: &Method, path: &str) -> Option<Scope> {
+    // 管理页面本身不需要 token, 页面内的请求再鉴权
+    if path == "/" || PUBLIC_PATHS.iter().any(|p| path.starts_with(p)) {
+        return None;
+    }
+
+    let scope = if path.starts_with("/app/package") {
+        Scope::Download
+    } else if path.starts_with("/app/query")
+        || path.starts_with("/app/log")
+        || path.starts_with("/app/group")
+        || path.starts_with("/app/workers")
+        || path.starts_with("/app/stats")
+        || path.starts_with("/worker/log")
+    {
+        Scope::Query
+    } else if (path.starts_with("/app/build") && method != Method::GET)
+        || (path.starts_with("/app/preset") && path.ends_with("/build"))
+    {
+        Scope::Submit
+    } else if path.starts_with("/app/preset") && method == Method::GET {
+        Scope::Query
+    } else {
+        Scope::Admin
+    };
+
+    Some(scope)
+}
+
+fn header_token(headers: &HeaderMap) -> Option<String> {
+    let v = headers.get("Authorization")?.to_str().ok()?.trim();
+    Some(v.strip_prefix("Bearer ").unwrap_or(v).trim().to_string())
+}
+
+/// 只有下载链接可以使用 `?token=`, 浏览器直接打开时无法设置请求头
+/// 访问日志使用 `%U` 只记录路径, 不含参数
+fn request_token(req: &ServiceRequest) -> Option<String> {
+    if let Some(token) = header_token(req.headers()) {
+        return Some(token);
+    }
+
+    if !req.path().starts_with("/app/package") {
+        return None;
+    }
+
+    url::form_urlencoded::parse(req.query_string().as_bytes())
+        .find(|(key, _)| key == "token")
+        .map(|(_, value)| value.to_string())
+}
+
+fn is_worker_token(token: &str) -> bool {
+    let worker_token = Config::worker_token();
+    !worker_token.is_empty() && const_eq(token.as_bytes(), worker_token.as_bytes())
+}
+
+/// 读取打包服务日志的权限, 打包服务上没有鉴权中间件, 只校验 worker token
+pub fn worker_authorized(req: &HttpRequest) -> bool {
+    if req.extensions().get::<Client>().is_some() || Config::worker_token().is_empty() {
+        return true;
+    }
+
+    header_token(req.headers())
+        .map(|t| is_worker_token(&t))
+        .unwrap_or(false)
+}
+
+async fn authorize(req: &ServiceRequest) -> Result<Option<Client>, Error> {
+    let admin_token = Config::admin_token();
+    if admin_token.is_empty() {
+        return Ok(None);
+    }
+
+    let scope = match required_scope(req.method(), req.path()) {
+        Some(s) => s,
+        None => return Ok(None),
+    };
+
+    let token = match request_token(req) {
+        Some(t) if !t.is_empty() => t,
+        _ => return Err(AppError::Unauthorized("missing token".to_string()).into()),
+    };
+
+    if const_eq(token.as_bytes(), admin_token.as_bytes()) {
+        return Ok(Some(Client {
+            name: ADMIN_NAME.to_string(),
+        }));
+    }
+
+    // worker token 只能读取打包服务日志
+    if req.path().starts_with("/worker/log") && is_worker_token(&token) {
+        return Ok(Some(Client {
+            name: WORKER_NAME.to_string(),
+        }));
+    }
+
+    let token = match Token::find(&token).await {
+        Ok(Some(t)) => t,
+        Ok(None) => return Err(AppError::Unauthorized("illegal token".to_string()).into()),
+        Err(e) => {
+            info!("find token error = {}", e);
+            return Err(e.into());
+        }
+    };
+
+    if token.is_expired() {
+        return Err(AppError::Unauthorized("token expired".to_string()).into());
+    }
+
+    if !token.has_scope(scope) {
+        return Err(AppError::Forbidden("permission denied".to_string()).into());
+    }
+
+    Ok(Some(Client { name: token.name }))
+}
+
+/// 请求的客户端名称, 未开启鉴权时为本机名称
+pub fn client_name(req: &HttpRequest) -> String {
+    match req.extensions().get::<Client>() {
+        Some(c) => c.name.clone(),
+        None => Config::ip(),
+    }
+}
+
+/// 限流使用的客户端标识, 有 token 时按 token, 否则按来源 ip
+pub fn rate_key(req: &HttpRequest) -> String {
+    if let Some(c) = req.extensions().get::<Client>() {
+        return format!("token:{}", c.name);
+    }
+
+    // 不使用 X-Forwarded-For 等请求头, 客户端可以随意伪造
+    let ip = req
+        .peer_addr()
+        .map(|a| a.ip().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    format!("ip:{}", ip)
+}
+
+/// token 鉴权中间件
+pub struct Auth;
+
+impl<S, B> Transform<S> for Auth
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type InitError = ();
+    type Transform = AuthMiddleware<S>;
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ok(AuthMiddleware {
+            service: Rc::new(RefCell::new(service)),
+        })
+    }
+}
+
+pub struct AuthMiddleware<S> {
+    service: Rc<RefCell<S>>,
+}
+
+impl<S, B> Service for AuthMiddleware<S>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Request = ServiceRequest;
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.service.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: ServiceRequest) -> Self::Future {
+        let mut service = self.service.clone();
+
+        Box::pin(async move {
+            if let Some(client) = authorize(&req).await? {
+                req.extensions_mut().insert(client);
+            }
+
+            service.call(req).await
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::Method, test::TestRequest};
+
+    use super::{rate_key, request_token, required_scope, Scope, Token};
+
+    #[test]
+    fn test_required_scope() {
+        assert_eq!(
+            required_scope(&Method::POST, "/app/build"),
+            Some(Scope::Submit)
+        );
+        assert_eq!(
+            required_scope(&Method::DELETE, "/app/build/123"),
+            Some(Scope::Submit)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/app/query/123"),
+            Some(Scope::Query)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/app/group/123"),
+            Some(Scope::Query)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/app/workers"),
+            Some(Scope::Query)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/app/build/matrix"),
+            Some(Scope::Submit)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/app/package/123.apk"),
+            Some(Scope::Download)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/app/token"),
+            Some(Scope::Admin)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/worker/log/123"),
+            Some(Scope::Query)
+        );
+        assert_eq!(required_scope(&Method::GET, "/"), None);
+        assert_eq!(required_scope(&Method::POST, "/hooks/gitlab"), None);
+        assert_eq!(
+            required_scope(&Method::POST, "/app/preset/customer/build"),
+            Some(Scope::Submit)
+        );
+        assert_eq!(
+            required_scope(&Method::GET, "/app/preset/customer"),
+            Some(Scope::Query)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/app/preset"),
+            Some(Scope::Admin)
+        );
+        assert_eq!(
+            required_scope(&Method::POST, "/app/trigger"),
+            Some(Scope::Admin)
+        );
+    }
+
+    #[test]
+    fn test_request_token() {
+        let req = TestRequest::get()
+            .uri("/app/query?token=abc")
+            .header("Authorization", "Bearer 123")
+            .to_srv_request();
+        assert_eq!(request_token(&req), Some("123".to_string()));
+
+        // 只有下载链接可以用参数传 token
+        let req = TestRequest::get()
+            .uri("/app/query?token=abc")
+            .to_srv_request();
+        assert_eq!(request_token(&req), None);
+
+        let req = TestRequest::get()
+            .uri("/app/package/123.apk?token=abc")
+            .to_srv_request();
+        assert_eq!(request_token(&req), Some("abc".to_string()));
+    }
+
+    #[test]
+    fn test_rate_key() {
+        let addr = "10.0.0.1:52000".parse().unwrap();
+        let key = |forwarded: &str| {
+            rate_key(
+                &TestRequest::default()
+                    .peer_addr(addr)
+                    .header("X-Forwarded-For", forwarded)
+                    .header("Forwarded", format!("for={}", forwarded))
+                    .to_http_request(),
+            )
+        };
+
+        // 伪造的请求头不影响限流
+        assert_eq!(key("1.1.1.1"), "ip:10.0.0.1");
+        assert_eq!(key("2.2.2.2"), key("1.1.1.1"));
+    }
+
+    #[test]
+    fn test_token_scope() {
+        let (token, raw) = Token::new("ci", vec![Scope::Submit, Scope::Query], None);
+        assert!(token.has_scope(Scope::Query));
+        assert!(!token.has_scope(Scope::Download));
+        assert!(!token.is_expired());
+
+        // 只保存 hash, 列表中只展示前 4 位
+        assert_eq!(token.token_hash, Token::hash(&raw));
+        assert_ne!(token.token_hash, raw);
+        let masked = token.masked();
+        assert_eq!(masked["token"], format!("{}****", &raw[..4]));
+        assert!(masked.get("token_hash").is_none());
+
+        let admin = Token::new("root", vec![Scope::Admin], None).0;
+        assert!(admin.has_scope(Scope::Download));
+    }
+}
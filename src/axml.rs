@@ -0,0 +1,580 @@
+use std::{collections::HashMap, fs::File, io::Read, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{build_params::BuildParams, error::AppError};
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_XML_TYPE: u16 = 0x0003;
+const RES_XML_START_ELEMENT_TYPE: u16 = 0x0102;
+const RES_XML_END_ELEMENT_TYPE: u16 = 0x0103;
+const RES_XML_RESOURCE_MAP_TYPE: u16 = 0x0180;
+
+const UTF8_FLAG: u32 = 1 << 8;
+const NO_INDEX: u32 = 0xffff_ffff;
+
+const TYPE_REFERENCE: u8 = 0x01;
+const TYPE_STRING: u8 = 0x03;
+const TYPE_FLOAT: u8 = 0x04;
+const TYPE_INT_DEC: u8 = 0x10;
+const TYPE_INT_HEX: u8 = 0x11;
+const TYPE_INT_BOOLEAN: u8 = 0x12;
+
+/// 编译后 manifest 中的一个节点, 属性名不含命名空间
+#[derive(Debug, Clone)]
+pub struct Element {
+    pub name: String,
+    pub parent: Option<String>,
+    pub attrs: HashMap<String, String>,
+}
+
+/// apk 的 manifest 信息
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct ApkMeta {
+    pub package: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_code: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_name: Option<String>,
+    /// 引用资源时为 `@0x7f...`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default)]
+    pub permissions: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_sdk: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub target_sdk: Option<i32>,
+    /// application 下的 meta-data
+    #[serde(default)]
+    pub meta: HashMap<String, String>,
+}
+
+fn illegal(msg: &str) -> AppError {
+    AppError::Verify(format!("illegal AndroidManifest.xml : {}", msg))
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Result<u16, AppError> {
+    data.get(pos..pos + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| illegal("unexpected end"))
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Result<u32, AppError> {
+    data.get(pos..pos + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| illegal("unexpected end"))
+}
+
+fn u8_at(data: &[u8], pos: usize) -> Result<u8, AppError> {
+    data.get(pos)
+        .copied()
+        .ok_or_else(|| illegal("unexpected end"))
+}
+
+/// 属性名被去掉时按资源 id 识别
+fn attr_name_by_id(id: u32) -> Option<&'static str> {
+    match id {
+        0x0101_0001 => Some("label"),
+        0x0101_0003 => Some("name"),
+        0x0101_0024 => Some("value"),
+        0x0101_020c => Some("minSdkVersion"),
+        0x0101_021b => Some("versionCode"),
+        0x0101_021c => Some("versionName"),
+        0x0101_0270 => Some("targetSdkVersion"),
+        _ => None,
+    }
+}
+
+/// utf8 字符串的长度, 超过 0x7f 时占两个字节
+fn utf8_len(data: &[u8], pos: usize) -> Result<(usize, usize), AppError> {
+    let b = u8_at(data, pos)? as usize;
+    if b & 0x80 != 0 {
+        let low = u8_at(data, pos + 1)? as usize;
+        Ok((((b & 0x7f) << 8) | low, pos + 2))
+    } else {
+        Ok((b, pos + 1))
+    }
+}
+
+fn utf8_string(data: &[u8], pos: usize) -> Result<String, AppError> {
+    // 先是 utf16 长度, 再是 utf8 字节数
+    let (_, pos) = utf8_len(data, pos)?;
+    let (len, pos) = utf8_len(data, pos)?;
+    let bytes = data
+        .get(pos..pos + len)
+        .ok_or_else(|| illegal("string out of range"))?;
+
+    Ok(String::from_utf8_lossy(bytes).to_string())
+}
+
+fn utf16_string(data: &[u8], pos: usize) -> Result<String, AppError> {
+    let mut len = u16_at(data, pos)? as usize;
+    let mut pos = pos + 2;
+    if len & 0x8000 != 0 {
+        len = ((len & 0x7fff) << 16) | u16_at(data, pos)? as usize;
+        pos += 2;
+    }
+
+    let units = (0..len)
+        .map(|i| u16_at(data, pos + i * 2))
+        .collect::<Result<Vec<u16>, AppError>>()?;
+
+    Ok(String::from_utf16_lossy(&units))
+}
+
+fn string_pool(data: &[u8], start: usize) -> Result<Vec<String>, AppError> {
+    let header_size = u16_at(data, start + 2)? as usize;
+    let count = u32_at(data, start + 8)? as usize;
+    let flags = u32_at(data, start + 16)?;
+    let strings_start = start + u32_at(data, start + 20)? as usize;
+
+    (0..count)
+        .map(|i| {
+            let offset = strings_start + u32_at(data, start + header_size + i * 4)? as usize;
+            if flags & UTF8_FLAG != 0 {
+                utf8_string(data, offset)
+            } else {
+                utf16_string(data, offset)
+            }
+        })
+        .collect()
+}
+
+fn string(strings: &[String], index: u32) -> String {
+    strings.get(index as usize).cloned().unwrap_or_default()
+}
+
+/// 属性值转为字符串, aapt 会把数字和布尔值编译成对应类型
+fn typed_value(strings: &[String], raw: u32, data_type: u8, data: u32) -> String {
+    match data_type {
+        TYPE_STRING if raw != NO_INDEX => string(strings, raw),
+        TYPE_STRING => string(strings, data),
+        TYPE_REFERENCE => format!("@0x{:08x}", data),
+        TYPE_INT_DEC => (data as i32).to_string(),
+        TYPE_INT_HEX => format!("0x{:x}", data),
+        TYPE_INT_BOOLEAN => (data != 0).to_string(),
+        TYPE_FLOAT => f32::from_bits(data).to_string(),
+        _ if raw != NO_INDEX => string(strings, raw),
+        _ => format!("0x{:08x}", data),
+    }
+}
+
+/// 解析二进制 xml
+pub fn parse(data: &[u8]) -> Result<Vec<Element>, AppError> {
+    if u16_at(data, 0)? != RES_XML_TYPE {
+        return Err(illegal("not a binary xml"));
+    }
+
+    let total = (u32_at(data, 4)? as usize).min(data.len());
+    let mut pos = u16_at(data, 2)? as usize;
+
+    let mut strings = Vec::new();
+    let mut ids = Vec::new();
+    let mut stack: Vec<String> = Vec::new();
+    let mut elements = Vec::new();
+
+    while pos + 8 <= total {
+        let kind = u16_at(data, pos)?;
+        let header_size = u16_at(data, pos + 2)? as usize;
+        let size = u32_at(data, pos + 4)? as usize;
+        if size < 8 {
+            return Err(illegal("chunk size"));
+        }
+
+        match kind {
+            RES_STRING_POOL_TYPE => strings = string_pool(data, pos)?,
+            RES_XML_RESOURCE_MAP_TYPE => {
+                ids = (pos + header_size..pos + size)
+                    .step_by(4)
+                    .map(|p| u32_at(data, p))
+                    .collect::<Result<Vec<u32>, AppError>>()?;
+            }
+            RES_XML_START_ELEMENT_TYPE => {
+                let ext = pos + header_size;
+                let name = string(&strings, u32_at(data, ext + 4)?);
+                let attr_start = u16_at(data, ext + 8)? as usize;
+                let attr_size = u16_at(data, ext + 10)? as usize;
+                let attr_count = u16_at(data, ext + 12)? as usize;
+
+                let mut attrs = HashMap::new();
+                for i in 0..attr_count {
+                    let a = ext + attr_start + i * attr_size;
+                    let name_index = u32_at(data, a + 4)?;
+                    let raw = u32_at(data, a + 8)?;
+                    let data_type = u8_at(data, a + 15)?;
+                    let value = u32_at(data, a + 16)?;
+
+                    let mut attr = string(&strings, name_index);
+                    if attr.is_empty() {
+                        if let Some(n) = ids
+                            .get(name_index as usize)
+                            .and_then(|id| attr_name_by_id(*id))
+                        {
+                            attr = n.to_string();
+                        }
+                    }
+
+                    attrs.insert(attr, typed_value(&strings, raw, data_type, value));
+                }
+
+                elements.push(Element {
+                    name: name.clone(),
+                    parent: stack.last().cloned(),
+                    attrs,
+                });
+                stack.push(name);
+            }
+            RES_XML_END_ELEMENT_TYPE => {
+                stack.pop();
+            }
+            _ => {}
+        }
+
+        pos += size;
+    }
+
+    Ok(elements)
+}
+
+impl ApkMeta {
+    pub fn from_elements(elements: &[Element]) -> ApkMeta {
+        let mut meta = ApkMeta::default();
+
+        for e in elements {
+            let attr = |name: &str| e.attrs.get(name).cloned();
+
+            match (e.name.as_str(), e.parent.as_deref()) {
+                ("manifest", None) => {
+                    meta.package = attr("package").unwrap_or_default();
+                    meta.version_code = attr("versionCode").and_then(|s| s.parse().ok());
+                    meta.version_name = attr("versionName");
+                }
+                ("uses-sdk", Some("manifest")) => {
+                    meta.min_sdk = attr("minSdkVersion").and_then(|s| s.parse().ok());
+                    meta.target_sdk = attr("targetSdkVersion").and_then(|s| s.parse().ok());
+                }
+                ("uses-permission", Some("manifest"))
+                | ("uses-permission-sdk-23", Some("manifest")) => {
+                    if let Some(name) = attr("name") {
+                        meta.permissions.push(name);
+                    }
+                }
+                ("application", Some("manifest")) => {
+                    meta.label = attr("label");
+                }
+                ("meta-data", Some("application")) => {
+                    if let (Some(name), Some(value)) = (attr("name"), attr("value")) {
+                        meta.meta.insert(name, value);
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        meta
+    }
+
+    /// 读取 apk 中的 AndroidManifest.xml
+    pub fn from_apk(path: &Path) -> Result<ApkMeta, AppError> {
+        let file = File::open(path)?;
+        let mut archive = zip::ZipArchive::new(file)
+            .map_err(|e| AppError::Verify(format!("open {} error : {}", path.display(), e)))?;
+        let mut manifest = archive
+            .by_name("AndroidManifest.xml")
+            .map_err(|e| AppError::Verify(format!("{} : {}", path.display(), e)))?;
+
+        let mut data = Vec::new();
+        manifest.read_to_end(&mut data)?;
+
+        Ok(ApkMeta::from_elements(&parse(&data)?))
+    }
+
+    /// 和打包参数比较, 返回不一致的地方
+    pub fn verify(&self, params: &BuildParams) -> Vec<String> {
+        let mut errors = Vec::new();
+
+        if let Some(code) = params.version.version_code {
+            if self.version_code != Some(code as i64) {
+                errors.push(format!(
+                    "versionCode expect {} but {:?}",
+                    code, self.version_code
+                ));
+            }
+        }
+
+        if let Some(ref name) = params.version.version_name {
+            if self.version_name.as_ref() != Some(name) {
+                errors.push(format!(
+                    "versionName expect {} but {:?}",
+                    name, self.version_name
+                ));
+            }
+        }
+
+        if let Some(ref c) = params.configs.base_config {
+            // 引用资源的名称无法在 manifest 中比较
+            if let Some(ref name) = c.app_name {
+                if !name.starts_with('@') && self.label.as_ref() != Some(name) {
+                    errors.push(format!("label expect {} but {:?}", name, self.label));
+                }
+            }
+
+            if let Some(ref m) = c.meta {
+                for (k, v) in m {
+                    match self.meta.get(k) {
+                        Some(actual) if same_value(actual, v) => {}
+                        actual => {
+                            errors.push(format!("meta-data {} expect {} but {:?}", k, v, actual))
+                        }
+                    }
+                }
+            }
+        }
+
+        errors
+    }
+}
+
+/// 数字和布尔值编译后格式可能变化, 按值比较
+fn same_value(actual: &str, expect: &str) -> bool {
+    if actual == expect {
+        return true;
+    }
+
+    match (actual.parse::<f64>(), expect.trim().parse::<f64>()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => actual.eq_ignore_ascii_case(expect.trim()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{parse, ApkMeta};
+    use crate::build_params::BuildParams;
+
+    /// 元素名和属性(名称, 类型, 值)
+    type Element<'a> = (&'a str, Vec<(&'a str, u8, u32)>);
+
+    /// 按 aapt 的格式生成 utf16 字符串池的二进制 xml
+    fn axml(strings: &[&str], elements: &[Element], depth: &[usize]) -> Vec<u8> {
+        fn u16s(v: &mut Vec<u8>, n: u16) {
+            v.extend_from_slice(&n.to_le_bytes());
+        }
+        fn u32s(v: &mut Vec<u8>, n: u32) {
+            v.extend_from_slice(&n.to_le_bytes());
+        }
+        let index = |s: &str| strings.iter().position(|x| *x == s).unwrap() as u32;
+
+        let mut data = Vec::new();
+        for s in strings {
+            let units = s.encode_utf16().collect::<Vec<u16>>();
+            u16s(&mut data, units.len() as u16);
+            units.iter().for_each(|u| u16s(&mut data, *u));
+            u16s(&mut data, 0);
+        }
+        while data.len() % 4 != 0 {
+            data.push(0);
+        }
+
+        let mut pool = Vec::new();
+        let header = 28 + strings.len() * 4;
+        u16s(&mut pool, 0x0001);
+        u16s(&mut pool, 28);
+        u32s(&mut pool, (header + data.len()) as u32);
+        u32s(&mut pool, strings.len() as u32);
+        u32s(&mut pool, 0);
+        u32s(&mut pool, 0);
+        u32s(&mut pool, header as u32);
+        u32s(&mut pool, 0);
+        let mut offset = 0;
+        for s in strings {
+            u32s(&mut pool, offset);
+            offset += 4 + s.encode_utf16().count() as u32 * 2;
+        }
+        pool.extend(data);
+
+        let mut body = Vec::new();
+        let mut open: Vec<&str> = Vec::new();
+        for ((name, attrs), d) in elements.iter().zip(depth) {
+            while open.len() > *d {
+                let end = open.pop().unwrap();
+                u16s(&mut body, 0x0103);
+                u16s(&mut body, 16);
+                u32s(&mut body, 24);
+                u32s(&mut body, 0);
+                u32s(&mut body, 0xffff_ffff);
+                u32s(&mut body, 0xffff_ffff);
+                u32s(&mut body, index(end));
+            }
+
+            u16s(&mut body, 0x0102);
+            u16s(&mut body, 16);
+            u32s(&mut body, (36 + attrs.len() * 20) as u32);
+            u32s(&mut body, 0);
+            u32s(&mut body, 0xffff_ffff);
+            u32s(&mut body, 0xffff_ffff);
+            u32s(&mut body, index(name));
+            u16s(&mut body, 20);
+            u16s(&mut body, 20);
+            u16s(&mut body, attrs.len() as u16);
+            u16s(&mut body, 0);
+            u16s(&mut body, 0);
+            u16s(&mut body, 0);
+            for (attr, data_type, value) in attrs {
+                u32s(&mut body, 0xffff_ffff);
+                u32s(&mut body, index(attr));
+                u32s(
+                    &mut body,
+                    if *data_type == 0x03 {
+                        *value
+                    } else {
+                        0xffff_ffff
+                    },
+                );
+                u16s(&mut body, 8);
+                body.push(0);
+                body.push(*data_type);
+                u32s(&mut body, *value);
+            }
+            open.push(*name);
+        }
+
+        let mut xml = Vec::new();
+        u16s(&mut xml, 0x0003);
+        u16s(&mut xml, 8);
+        u32s(&mut xml, (8 + pool.len() + body.len()) as u32);
+        xml.extend(pool);
+        xml.extend(body);
+        xml
+    }
+
+    fn manifest() -> Vec<u8> {
+        let strings = [
+            "manifest",
+            "package",
+            "versionCode",
+            "versionName",
+            "uses-sdk",
+            "minSdkVersion",
+            "targetSdkVersion",
+            "uses-permission",
+            "name",
+            "application",
+            "label",
+            "meta-data",
+            "value",
+            "activity",
+            "com.example.seed",
+            "1.2.0",
+            "android.permission.INTERNET",
+            "种子",
+            "channel",
+            "huawei",
+            "is_overseas",
+            "debug_level",
+        ];
+        let s = |v: &str| strings.iter().position(|x| *x == v).unwrap() as u32;
+
+        axml(
+            &strings,
+            &[
+                (
+                    "manifest",
+                    vec![
+                        ("package", 0x03, s("com.example.seed")),
+                        ("versionCode", 0x10, 120),
+                        ("versionName", 0x03, s("1.2.0")),
+                    ],
+                ),
+                (
+                    "uses-sdk",
+                    vec![("minSdkVersion", 0x10, 21), ("targetSdkVersion", 0x10, 30)],
+                ),
+                (
+                    "uses-permission",
+                    vec![("name", 0x03, s("android.permission.INTERNET"))],
+                ),
+                ("application", vec![("label", 0x03, s("种子"))]),
+                (
+                    "meta-data",
+                    vec![("name", 0x03, s("channel")), ("value", 0x03, s("huawei"))],
+                ),
+                (
+                    "meta-data",
+                    vec![
+                        ("name", 0x03, s("is_overseas")),
+                        ("value", 0x12, 0xffff_ffff),
+                    ],
+                ),
+                ("activity", vec![]),
+                (
+                    "meta-data",
+                    vec![("name", 0x03, s("debug_level")), ("value", 0x10, 3)],
+                ),
+            ],
+            &[0, 1, 1, 1, 2, 2, 2, 3],
+        )
+    }
+
+    #[test]
+    fn test_parse() {
+        let elements = parse(&manifest()).unwrap();
+        assert_eq!(elements.len(), 8);
+        assert_eq!(elements[3].name, "application");
+        assert_eq!(elements[7].parent, Some("activity".to_string()));
+
+        let meta = ApkMeta::from_elements(&elements);
+        assert_eq!(meta.package, "com.example.seed");
+        assert_eq!(meta.version_code, Some(120));
+        assert_eq!(meta.version_name, Some("1.2.0".to_string()));
+        assert_eq!(meta.label, Some("种子".to_string()));
+        assert_eq!(meta.min_sdk, Some(21));
+        assert_eq!(meta.target_sdk, Some(30));
+        assert_eq!(meta.permissions, vec!["android.permission.INTERNET"]);
+        assert_eq!(meta.meta.get("channel"), Some(&"huawei".to_string()));
+        assert_eq!(meta.meta.get("is_overseas"), Some(&"true".to_string()));
+        // activity 下的 meta-data 不算
+        assert!(!meta.meta.contains_key("debug_level"));
+
+        assert!(parse(b"not a manifest").is_err());
+        assert!(parse(&manifest()[..100]).is_err());
+    }
+
+    #[test]
+    fn test_verify() {
+        let meta = ApkMeta::from_elements(&parse(&manifest()).unwrap());
+
+        let mut params: BuildParams = serde_json::from_str(
+            r#"{
+                "version": {
+                    "project_name": "seed",
+                    "source_url": "ssh://git@git.example.com/seed.git",
+                    "version_code": 120,
+                    "version_name": "1.2.0"
+                },
+                "configs": {
+                    "framework": "normal",
+                    "base_config": {
+                        "app_name": "种子",
+                        "meta": {"channel": "huawei", "is_overseas": "TRUE"}
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+        assert!(meta.verify(&params).is_empty());
+
+        params.version.version_code = Some(121);
+        let mut m = HashMap::new();
+        m.insert("channel".to_string(), "xiaomi".to_string());
+        params.configs.base_config.as_mut().unwrap().meta = Some(m);
+
+        let errors = meta.verify(&params);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].starts_with("versionCode"));
+        assert!(errors[1].starts_with("meta-data channel"));
+    }
+}
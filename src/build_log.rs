@@ -0,0 +1,173 @@
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    time::Duration,
+};
+
+use actix_web::{web::Bytes, Error};
+use futures::{stream, Stream};
+use log::info;
+use uuid::Uuid;
+
+use crate::{
+    build_params::AppParams, config, error::AppError, get_default, get_upload_url, result_err,
+    utils, work::get_log_file, worker::Worker,
+};
+
+pub const HEADER_BUILDING: &str = "X-Log-Building";
+pub const LOG_CONTENT_TYPE: &str = "text/plain; charset=utf-8";
+
+/// 单次读取日志的最大长度
+const MAX_CHUNK: u64 = 512 * 1024;
+/// 日志无输出时的最大等待次数
+const MAX_IDLE: u32 = 600;
+
+/// 读取本机打包日志
+pub fn read_local_log(build_id: Uuid, offset: u64) -> Result<Vec<u8>, AppError> {
+    let path = get_log_file(build_id);
+    if !utils::file_exist(&path) {
+        return Ok(Vec::new());
+    }
+
+    let mut file = File::open(&path)?;
+    let len = file.metadata()?.len();
+    if offset >= len {
+        return Ok(Vec::new());
+    }
+
+    file.seek(SeekFrom::Start(offset))?;
+
+    let mut buf = Vec::new();
+    file.take(MAX_CHUNK).read_to_end(&mut buf)?;
+
+    Ok(buf)
+}
+
+/// 打包服务的http地址
+pub async fn worker_addr(app: &AppParams) -> Option<String> {
+    let name = get_default!(app.operate);
+    if name.is_empty() {
+        return None;
+    }
+
+    let worker = Worker::find(&name).await?;
+    if worker.is_live(chrono::Utc::now().timestamp_millis()) {
+        Some(worker.addr)
+    } else {
+        None
+    }
+}
+
+async fn fetch_worker_log(addr: &str, id: &str, offset: u64) -> Result<(Vec<u8>, bool), AppError> {
+    let mut request = reqwest::Client::new()
+        .get(format!("http://{}/worker/log/{}?offset={}", addr, id, offset).as_str());
+
+    // 只发送 worker token, 不把管理员 token 发给打包服务
+    let token = config::Config::worker_token();
+    if !token.is_empty() {
+        request = request.bearer_auth(token);
+    }
+
+    let res = request
+        .send()
+        .await
+        .map_err(result_err!(AppError::Internal))?;
+
+    if !res.status().is_success() {
+        return Err(AppError::Internal(format!(
+            "fetch worker log error, status = {}",
+            res.status()
+        )));
+    }
+
+    let building = res
+        .headers()
+        .get(HEADER_BUILDING)
+        .map(|v| v.to_str().unwrap_or("") == "true")
+        .unwrap_or(false);
+
+    let bytes = res.bytes().await.map_err(result_err!(AppError::Internal))?;
+
+    Ok((bytes.to_vec(), building))
+}
+
+async fn fetch_upload_log(fid: &str, offset: u64) -> Result<Vec<u8>, AppError> {
+    let res = reqwest::get(get_upload_url!(fid))
+        .await
+        .map_err(result_err!(AppError::Upload))?;
+
+    if !res.status().is_success() {
+        return Err(AppError::Upload(format!(
+            "fetch log error, status = {}",
+            res.status()
+        )));
+    }
+
+    let bytes = res.bytes().await.map_err(result_err!(AppError::Upload))?;
+    let offset = std::cmp::min(offset as usize, bytes.len());
+
+    Ok(bytes[offset..].to_vec())
+}
+
+/// 已结束任务的日志, 优先从文件服务器读取
+pub async fn stored_log(app: &AppParams, offset: u64) -> Result<Vec<u8>, AppError> {
+    let fid = get_default!(app.log_fid);
+    let id = app.build_id.to_string();
+    let addr = worker_addr(app).await;
+
+    config::get_runtime()
+        .spawn(async move {
+            if !fid.is_empty() {
+                fetch_upload_log(&fid, offset).await
+            } else if let Some(addr) = addr {
+                fetch_worker_log(&addr, &id, offset).await.map(|f| f.0)
+            } else {
+                Err(AppError::NotFound("日志不存在".to_string()))
+            }
+        })
+        .await
+        .map_err(result_err!(AppError::Internal))?
+}
+
+/// 持续读取打包服务上正在编译的日志
+pub fn follow_log(
+    addr: String,
+    id: String,
+    offset: u64,
+) -> impl Stream<Item = Result<Bytes, Error>> {
+    stream::unfold(offset, move |offset| {
+        let addr = addr.clone();
+        let id = id.clone();
+
+        async move {
+            let result = config::get_runtime()
+                .spawn(async move {
+                    let mut idle = 0;
+                    while idle < MAX_IDLE {
+                        match fetch_worker_log(&addr, &id, offset).await {
+                            Ok((data, _)) if !data.is_empty() => return Some(data),
+                            Ok((_, true)) => {
+                                idle += 1;
+                                tokio::time::sleep(Duration::from_millis(1000)).await;
+                            }
+                            Ok(_) => return None,
+                            Err(err) => {
+                                info!("follow log {} error = {}", id, err);
+                                return None;
+                            }
+                        }
+                    }
+                    None
+                })
+                .await;
+
+            match result {
+                Ok(Some(data)) => {
+                    let next = offset + data.len() as u64;
+                    Some((Ok(Bytes::from(data)), next))
+                }
+                _ => None,
+            }
+        }
+    })
+}
@@ -1,5 +1,6 @@
-use bson::DateTime;
+use bson::{doc, Bson, DateTime};
 use log::info;
+use mongodb::options::FindOneOptions;
 use serde::{Deserialize, Serialize, Serializer};
 use url::Url;
 use uuid::Uuid;
@@ -15,11 +16,16 @@ pub fn bson_datetime_as_iso_string<S: Serializer>(
 }
 
 use crate::{
+    artifact::Artifact,
+    axml::ApkMeta,
     db::{Db, COLLECTION_BUILD},
+    error::AppError,
     filter_build_id,
+    preset::PresetRef,
+    queue::QueueInfo,
 };
 
-#[derive(Debug, Serialize, Deserialize, PartialEq)]
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
 pub enum Framework {
     // #[serde(rename = "mdm_4")]
     // Mdm4,
@@ -38,7 +44,7 @@ pub enum Scm {
     #[serde(rename = "git")]
     Git,
 }
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Version {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub project_name: Option<String>,
@@ -48,6 +54,15 @@ pub struct Version {
     pub source_url: Url,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub channel: Option<String>,
+    /// 各 flavor 维度的值, 按 flavorDimensions 的顺序, 未设置时使用 channel
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flavors: Option<Vec<String>>,
+    /// 编译类型, debug/release 或自定义的 buildType, 默认 release
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_type: Option<String>,
+    /// 是否同时执行 bundle 任务生成 aab, 默认 false
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<bool>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub branch: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -58,7 +73,91 @@ pub struct Version {
     pub version_name: Option<String>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// gradle 编译变体, 由 flavors 和 build_type 组成
+#[derive(Debug, PartialEq, Clone)]
+pub struct Variant {
+    pub flavors: Vec<String>,
+    pub build_type: String,
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) => c.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+impl Version {
+    pub fn variant(&self) -> Result<Variant, AppError> {
+        let flavors = match (&self.flavors, &self.channel) {
+            (Some(f), _) => f.clone(),
+            (None, Some(c)) => vec![c.clone()],
+            (None, None) => Vec::new(),
+        };
+        let build_type = self
+            .build_type
+            .clone()
+            .unwrap_or_else(|| "release".to_string());
+
+        for name in flavors.iter().chain(std::iter::once(&build_type)) {
+            let legal = name.chars().next().map(|c| c.is_ascii_alphabetic()) == Some(true)
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+            if !legal {
+                return Err(AppError::Validation(format!(
+                    "illegal flavor or build_type {}",
+                    name
+                )));
+            }
+        }
+
+        Ok(Variant {
+            flavors,
+            build_type,
+        })
+    }
+}
+
+impl Variant {
+    /// 组合后的 flavor 名称, 例如 `huaweiProd`
+    pub fn flavor_name(&self) -> String {
+        self.flavors
+            .iter()
+            .enumerate()
+            .map(|(i, f)| if i == 0 { f.clone() } else { capitalize(f) })
+            .collect()
+    }
+
+    /// 变体名称, 例如 `huaweiProdStaging`
+    pub fn name(&self) -> String {
+        if self.flavors.is_empty() {
+            self.build_type.clone()
+        } else {
+            self.flavor_name() + &capitalize(&self.build_type)
+        }
+    }
+
+    /// assemble 任务名称, 例如 `assembleHuaweiProdStaging`
+    pub fn task(&self) -> String {
+        format!("assemble{}", capitalize(&self.name()))
+    }
+
+    /// bundle 任务名称, 例如 `bundleHuaweiProdStaging`
+    pub fn bundle_task(&self) -> String {
+        format!("bundle{}", capitalize(&self.name()))
+    }
+
+    /// outputs 下的 apk 目录, 例如 `apk/huaweiProd/staging`
+    pub fn apk_dir(&self) -> String {
+        if self.flavors.is_empty() {
+            format!("apk/{}", self.build_type)
+        } else {
+            format!("apk/{}/{}", self.flavor_name(), self.build_type)
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Configs {
     // 打包框架
     pub framework: Framework,
@@ -69,7 +168,7 @@ pub struct Configs {
     pub app_config: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BaseConfig {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub app_name: Option<String>,
@@ -77,11 +176,14 @@ pub struct BaseConfig {
     pub app_icon: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub assets_config: Option<Url>,
+    /// assets_config 压缩包的 sha256, 设置后下载完校验
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub assets_sha256: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub meta: Option<HashMap<String, String>>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct BuildParams {
     pub version: Version,
     pub configs: Configs,
@@ -89,6 +191,165 @@ pub struct BuildParams {
     pub email: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", rename = "responseUrl")]
     pub response_url: Option<Url>,
+    /// 优先级, 越大越先打包, 默认 0
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    /// 重复提交时返回已有任务, 也可以用请求头 `Idempotency-Key`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    /// zkm 混淆
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub obfuscate: Option<Obfuscate>,
+    /// 使用 manager 保存的签名证书签名
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub signing: Option<Signing>,
+}
+
+/// zkm 混淆, 在编译之后签名之前执行
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Obfuscate {
+    /// zkm 脚本, 相对源码目录
+    pub script: String,
+    /// 脚本中 changeLogFileOut 的路径, 相对源码目录, 默认 `ChangeLog.txt`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub change_log: Option<String>,
+    /// 混淆后重新打包的 gradle 参数, 例如 `assembleRelease -x compileReleaseJavaWithJavac`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gradle_task: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Signing {
+    /// `/app/keystore` 中的名称
+    pub keystore: String,
+}
+
+impl BuildParams {
+    /// 比较打包参数, 忽略 idempotency_key
+    pub fn same_as(&self, other: &BuildParams) -> bool {
+        let value = |p: &BuildParams| {
+            let mut v = serde_json::to_value(p).unwrap();
+            if let Some(map) = v.as_object_mut() {
+                map.remove("idempotency_key");
+            }
+            v
+        };
+
+        value(self) == value(other)
+    }
+
+    /// 入队前检查会拼接到命令行中的参数
+    pub fn validate(&self) -> Result<(), AppError> {
+        match &self.obfuscate {
+            Some(o) => o.validate(),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Obfuscate {
+    /// gradle_task 只能是空格分隔的任务名和参数, 字符为 `[A-Za-z0-9:_-]`
+    pub fn validate(&self) -> Result<(), AppError> {
+        if let Some(ref task) = self.gradle_task {
+            let legal = !task.trim().is_empty()
+                && task.chars().all(|c| {
+                    c == ' ' || c.is_ascii_alphanumeric() || c == ':' || c == '_' || c == '-'
+                });
+            if !legal {
+                return Err(AppError::Validation(format!(
+                    "illegal obfuscate gradle_task {}",
+                    task
+                )));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// 重新打包, 批量打包等场景下对原参数的覆盖
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct BuildOverride {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub flavors: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_type: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version_name: Option<String>,
+    /// 替换原有 base_config
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_config: Option<BaseConfig>,
+    /// 与原有 app_config 合并, 同名的值覆盖
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_config: Option<HashMap<String, String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email: Option<String>,
+}
+
+impl BuildOverride {
+    pub fn apply(&self, params: &mut BuildParams) {
+        if self.branch.is_some() {
+            params.version.branch = self.branch.clone();
+            // 切换分支后原 revision 不再有效
+            params.version.revision = None;
+        }
+
+        if self.revision.is_some() {
+            params.version.revision = self.revision.clone();
+        }
+
+        if self.channel.is_some() {
+            params.version.channel = self.channel.clone();
+            // flavors 优先于 channel, 只覆盖 channel 时清空原有 flavors
+            params.version.flavors = None;
+        }
+
+        if self.flavors.is_some() {
+            params.version.flavors = self.flavors.clone();
+        }
+
+        if self.build_type.is_some() {
+            params.version.build_type = self.build_type.clone();
+        }
+
+        if self.version_code.is_some() {
+            params.version.version_code = self.version_code;
+        }
+
+        if self.version_name.is_some() {
+            params.version.version_name = self.version_name.clone();
+        }
+
+        if self.base_config.is_some() {
+            params.configs.base_config = self.base_config.clone();
+        }
+
+        if self.email.is_some() {
+            params.email = self.email.clone();
+        }
+
+        if let Some(ref config) = self.app_config {
+            let app_config = params.configs.app_config.get_or_insert_with(HashMap::new);
+            for (key, value) in config {
+                app_config.insert(key.clone(), value.clone());
+            }
+        }
+    }
+}
+
+/// 同一份参数批量打包多个配置
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BuildMatrix {
+    pub base: BuildParams,
+    pub variants: Vec<BuildOverride>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -99,8 +360,10 @@ pub struct BuildStatus {
 
 const CODE_SUCCESS: i32 = 0;
 pub const CODE_ILLEGAL: i32 = -1;
+pub const CODE_FAILED: i32 = 1;
 pub const CODE_WAITING: i32 = 2;
 pub const CODE_BUILDING: i32 = 3;
+pub const CODE_CANCELLED: i32 = 4;
 
 pub const MSG_ILLEGAL: &'static str = "非法id";
 
@@ -116,8 +379,22 @@ impl BuildStatus {
         self.code == CODE_SUCCESS
     }
 
+    pub fn is_finished(&self) -> bool {
+        self.code == CODE_SUCCESS || self.code == CODE_FAILED || self.code == CODE_CANCELLED
+    }
+
     pub fn failed(msg: String) -> Self {
-        BuildStatus { code: 1, msg }
+        BuildStatus {
+            code: CODE_FAILED,
+            msg,
+        }
+    }
+
+    pub fn cancelled() -> Self {
+        BuildStatus {
+            code: CODE_CANCELLED,
+            msg: String::from("已取消"),
+        }
     }
 
     pub fn waiting() -> Self {
@@ -158,8 +435,30 @@ pub struct AppParams {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fid: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<Vec<Artifact>>,
+    /// 从 apk 中读取的 manifest 信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apk: Option<ApkMeta>,
+    /// 签名证书的 sha256
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub operate: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_fid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<PresetRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub start_time: Option<DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub update_time: Option<DateTime>,
 }
 
@@ -179,9 +478,34 @@ pub struct AppParams2 {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub fid: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<Vec<Artifact>>,
+    /// 从 apk 中读取的 manifest 信息
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apk: Option<ApkMeta>,
+    /// 签名证书的 sha256
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_sha256: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
     pub operate: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_fid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub origin_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group_id: Option<Uuid>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<PresetRef>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    #[serde(skip_serializing)]
+    pub start_time: Option<DateTime>,
     #[serde(skip_serializing)]
     pub update_time: Option<DateTime>,
+    /// 等待中的任务返回排队信息
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    pub queue: Option<QueueInfo>,
 }
 
 impl AppParams {
@@ -196,17 +520,57 @@ impl AppParams {
             build_time: 0,
             email,
             fid: Some("".to_string()),
+            artifacts: None,
+            apk: None,
+            cert_sha256: None,
             operate: Some(operate.to_string()),
+            start_time: None,
             update_time: Some(date),
+            log_fid: None,
+            origin_id: None,
+            client: None,
+            group_id: None,
+            preset: None,
+            idempotency_key: None,
+        }
+    }
+
+    pub async fn find_by_id(id: &str) -> Result<AppParams, AppError> {
+        let result = Db::find_one(COLLECTION_BUILD, filter_build_id!(id), None).await?;
+
+        match result {
+            Some(doc) => Ok(bson::from_bson::<AppParams>(Bson::Document(doc))?),
+            None => Err(AppError::NotFound(MSG_ILLEGAL.to_string())),
         }
     }
 
-    pub async fn save_db(&self) -> Result<(), String> {
+    /// 同一客户端在 since 之后用相同 key 提交的任务
+    pub async fn find_by_idempotency(
+        client: &str,
+        key: &str,
+        since: DateTime,
+    ) -> Result<Option<AppParams>, AppError> {
+        let filter = doc! {
+            "client": client,
+            "idempotency_key": key,
+            "date": {"$gte": Bson::DateTime(since.0)},
+        };
+        let options = FindOneOptions::builder().sort(doc! {"date": -1}).build();
+
+        let result = Db::find_one(COLLECTION_BUILD, filter, options).await?;
+
+        match result {
+            Some(doc) => Ok(Some(bson::from_bson::<AppParams>(Bson::Document(doc))?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn save_db(&self) -> Result<(), AppError> {
         let doc = match bson::to_bson(&self) {
             Ok(d) => d.as_document().unwrap().clone(),
             Err(e) => {
                 info!("to_bson err {}", e);
-                return Err(format!("to_bson error : {}", e));
+                return Err(e.into());
             }
         };
 
@@ -218,7 +582,7 @@ impl AppParams {
         .await
         {
             info!("db save error{} ", e);
-            return Err(format!("db save error{} ", e));
+            return Err(e);
         }
         Ok(())
     }
@@ -226,7 +590,7 @@ impl AppParams {
 
 #[cfg(test)]
 mod tests {
-    use super::{BuildParams, Framework, Scm};
+    use super::{BuildOverride, BuildParams, Framework, Obfuscate, Scm, Variant};
     use serde_json::Result;
 
     fn typed_example() -> Result<BuildParams> {
@@ -279,4 +643,128 @@ mod tests {
         assert_eq!(params.version.scm.unwrap(), Scm::Git);
         assert_eq!(params.configs.framework, Framework::Normal);
     }
+
+    #[test]
+    fn params_variant() {
+        let mut params = typed_example().unwrap();
+
+        let variant = params.version.variant().unwrap();
+        assert_eq!(variant.task(), "assembleMasterRelease");
+        assert_eq!(variant.apk_dir(), "apk/master/release");
+
+        params.version.flavors = Some(vec!["huawei".to_string(), "prod".to_string()]);
+        params.version.build_type = Some("staging".to_string());
+        let variant = params.version.variant().unwrap();
+        assert_eq!(variant.name(), "huaweiProdStaging");
+        assert_eq!(variant.task(), "assembleHuaweiProdStaging");
+        assert_eq!(variant.bundle_task(), "bundleHuaweiProdStaging");
+        assert_eq!(variant.apk_dir(), "apk/huaweiProd/staging");
+
+        let variant = Variant {
+            flavors: Vec::new(),
+            build_type: "debug".to_string(),
+        };
+        assert_eq!(variant.task(), "assembleDebug");
+        assert_eq!(variant.apk_dir(), "apk/debug");
+
+        params.version.build_type = Some("release; rm -rf /".to_string());
+        assert!(params.version.variant().is_err());
+    }
+
+    #[test]
+    fn params_validate() {
+        let mut params = typed_example().unwrap();
+        assert!(params.validate().is_ok());
+
+        let mut obfuscate = Obfuscate {
+            script: "zkm/script.txt".to_string(),
+            change_log: None,
+            gradle_task: Some("assembleRelease -x compileReleaseJavaWithJavac".to_string()),
+        };
+        params.obfuscate = Some(obfuscate.clone());
+        assert!(params.validate().is_ok());
+
+        obfuscate.gradle_task = Some(":app:assembleRelease --offline".to_string());
+        assert!(obfuscate.validate().is_ok());
+
+        for task in &["assembleRelease; rm -rf /", "$(id)", "a > /tmp/x", " "] {
+            obfuscate.gradle_task = Some(task.to_string());
+            params.obfuscate = Some(obfuscate.clone());
+            assert!(params.validate().is_err());
+        }
+    }
+
+    #[test]
+    fn params_same_as() {
+        let params = typed_example().unwrap();
+
+        let mut other = params.clone();
+        other.idempotency_key = Some("release-5.0.20201111r1".to_string());
+        assert!(params.same_as(&other));
+
+        other.version.version_code = Some(20111102);
+        assert!(!params.same_as(&other));
+    }
+
+    #[test]
+    fn override_apply() {
+        let mut params = typed_example().unwrap();
+        params.version.revision = Some("e9406d9d".to_string());
+
+        let patch: BuildOverride =
+            serde_json::from_str(r#"{ "branch": "release", "version_code": 20111102 }"#).unwrap();
+        patch.apply(&mut params);
+
+        assert_eq!(params.version.branch.unwrap(), "release");
+        assert_eq!(params.version.revision, None);
+        assert_eq!(params.version.version_code, Some(20111102));
+        assert_eq!(params.version.version_name.unwrap(), "5.0.20201111r1");
+    }
+
+    #[test]
+    fn override_apply_channel() {
+        let mut params = typed_example().unwrap();
+        params.version.flavors = Some(vec!["huawei".to_string(), "prod".to_string()]);
+
+        let patch: BuildOverride = serde_json::from_str(r#"{ "channel": "xiaomi" }"#).unwrap();
+        patch.apply(&mut params);
+
+        assert_eq!(params.version.flavors, None);
+        assert_eq!(params.version.variant().unwrap().name(), "xiaomiRelease");
+
+        let patch: BuildOverride =
+            serde_json::from_str(r#"{ "channel": "oppo", "flavors": ["oppo", "dev"] }"#).unwrap();
+        patch.apply(&mut params);
+        assert_eq!(params.version.variant().unwrap().name(), "oppoDevRelease");
+    }
+
+    #[test]
+    fn override_apply_configs() {
+        let mut params = typed_example().unwrap();
+
+        let patch: BuildOverride = serde_json::from_str(
+            r#"{
+                "channel": "huawei",
+                "version_name": "5.0.20201111r2",
+                "app_config": { "is_overseas": "true", "server": "10.0.0.1" },
+                "base_config": { "app_name": "seed" },
+                "email": "sunzhiwei@justsafe.com"
+            }"#,
+        )
+        .unwrap();
+        patch.apply(&mut params);
+
+        assert_eq!(params.version.channel.unwrap(), "huawei");
+        assert_eq!(params.version.version_name.unwrap(), "5.0.20201111r2");
+        assert_eq!(params.version.version_code, Some(20111101));
+
+        let app_config = params.configs.app_config.unwrap();
+        assert_eq!(app_config.get("is_overseas").unwrap(), "true");
+        assert_eq!(app_config.get("server").unwrap(), "10.0.0.1");
+        assert_eq!(app_config.get("is_check_root").unwrap(), "true");
+
+        let base_config = params.configs.base_config.unwrap();
+        assert_eq!(base_config.app_name.unwrap(), "seed");
+        assert_eq!(params.email.unwrap(), "sunzhiwei@justsafe.com");
+    }
 }
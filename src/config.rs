@@ -9,9 +9,20 @@ pub struct Config {
     pub android_home: String,
     pub cache_home: String,
     pub building: bool,
+    pub build_id: Option<String>,
+    pub cancelled: bool,
     pub ding: bool,
     pub no_upload: bool,
     pub ip: String,
+    pub addr: String,
+    pub admin_token: String,
+    pub hook_secret: String,
+    pub keystore_secret: String,
+    pub worker_token: String,
+    pub idempotency_window: i64,
+    pub rate_limit: i64,
+    pub rate_window: i64,
+    pub max_waiting: i64,
 }
 
 static RUNTIME: OnceCell<Runtime> = OnceCell::new();
@@ -40,9 +51,20 @@ impl Config {
                         android_home: "/opt/android/sdk".to_string(),
                         cache_home: format!("{}/.mdm_build", env::var("HOME").unwrap()).to_string(),
                         building: false,
+                        build_id: None,
+                        cancelled: false,
                         ding: false,
                         no_upload: false,
                         ip: whoami::hostname(),
+                        addr: String::new(),
+                        admin_token: String::new(),
+                        hook_secret: String::new(),
+                        keystore_secret: String::new(),
+                        worker_token: String::new(),
+                        idempotency_window: 60 * 60 * 24,
+                        rate_limit: 0,
+                        rate_window: 60,
+                        max_waiting: 0,
                     }))
                 })
                 .clone()
@@ -57,6 +79,39 @@ impl Config {
         self.ip = ip.to_string();
     }
 
+    pub fn set_addr(&mut self, addr: &str) {
+        self.addr = addr.to_string();
+    }
+
+    pub fn set_admin_token(&mut self, token: &str) {
+        self.admin_token = token.to_string();
+    }
+
+    pub fn set_hook_secret(&mut self, secret: &str) {
+        self.hook_secret = secret.to_string();
+    }
+
+    pub fn set_keystore_secret(&mut self, secret: &str) {
+        self.keystore_secret = secret.to_string();
+    }
+
+    pub fn set_worker_token(&mut self, token: &str) {
+        self.worker_token = token.to_string();
+    }
+
+    pub fn set_idempotency_window(&mut self, window: i64) {
+        self.idempotency_window = window;
+    }
+
+    pub fn set_rate_limit(&mut self, limit: i64, window: i64) {
+        self.rate_limit = limit;
+        self.rate_window = window;
+    }
+
+    pub fn set_max_waiting(&mut self, max: i64) {
+        self.max_waiting = max;
+    }
+
     pub fn set_android_home(&mut self, android: &str) {
         self.android_home = android.to_string();
     }
@@ -65,6 +120,14 @@ impl Config {
         self.building = building;
     }
 
+    pub fn set_build_id(&mut self, build_id: Option<String>) {
+        self.build_id = build_id;
+    }
+
+    pub fn set_cancelled(&mut self, cancelled: bool) {
+        self.cancelled = cancelled;
+    }
+
     pub fn set_ding(&mut self, ding: bool) {
         self.ding = ding;
     }
@@ -85,6 +148,14 @@ impl Config {
         Config::get_instance().lock().unwrap().building
     }
 
+    pub fn build_id() -> Option<String> {
+        Config::get_instance().lock().unwrap().build_id.clone()
+    }
+
+    pub fn is_cancelled() -> bool {
+        Config::get_instance().lock().unwrap().cancelled
+    }
+
     pub fn enable_ding() -> bool {
         Config::get_instance().lock().unwrap().ding
     }
@@ -97,7 +168,56 @@ impl Config {
         Config::get_instance().lock().unwrap().ip.clone()
     }
 
+    pub fn addr() -> String {
+        Config::get_instance().lock().unwrap().addr.clone()
+    }
+
+    pub fn admin_token() -> String {
+        Config::get_instance().lock().unwrap().admin_token.clone()
+    }
+
+    pub fn hook_secret() -> String {
+        Config::get_instance().lock().unwrap().hook_secret.clone()
+    }
+
+    pub fn keystore_secret() -> String {
+        Config::get_instance()
+            .lock()
+            .unwrap()
+            .keystore_secret
+            .clone()
+    }
+
+    pub fn worker_token() -> String {
+        Config::get_instance().lock().unwrap().worker_token.clone()
+    }
+
+    pub fn idempotency_window() -> i64 {
+        Config::get_instance().lock().unwrap().idempotency_window
+    }
+
+    pub fn rate_limit() -> (i64, i64) {
+        let config = Config::get_instance();
+        let config = config.lock().unwrap();
+        (config.rate_limit, config.rate_window)
+    }
+
+    pub fn max_waiting() -> i64 {
+        Config::get_instance().lock().unwrap().max_waiting
+    }
+
     pub fn change_building(b: bool) {
         Config::get_instance().lock().unwrap().set_building(b);
     }
+
+    pub fn change_build_id(build_id: Option<String>) {
+        Config::get_instance()
+            .lock()
+            .unwrap()
+            .set_build_id(build_id);
+    }
+
+    pub fn change_cancelled(b: bool) {
+        Config::get_instance().lock().unwrap().set_cancelled(b);
+    }
 }
@@ -3,7 +3,6 @@ use std::{sync::Arc, time::Duration};
 use bson::{Bson, Document};
 use log::info;
 use mongodb::{
-    error::Error,
     options::{ClientOptions, FindOneOptions, FindOptions},
     Client,
 };
@@ -13,6 +12,8 @@ use tokio_stream::StreamExt;
 
 use std::result::Result;
 
+use crate::error::AppError;
+
 #[macro_export]
 macro_rules! filter_build_id {
     ($e:expr) => {
@@ -25,6 +26,11 @@ pub struct Db;
 
 const TABLE_NAME: &'static str = "build_data";
 pub const COLLECTION_BUILD: &'static str = "build";
+pub const COLLECTION_TOKEN: &str = "token";
+pub const COLLECTION_TRIGGER: &str = "trigger";
+pub const COLLECTION_PRESET: &str = "preset";
+pub const COLLECTION_SCHEDULE: &str = "schedule";
+pub const COLLECTION_KEYSTORE: &str = "keystore";
 const KEY_UPDATE_TIME: &'static str = "update_time";
 
 static INSTANCE: OnceCell<Arc<Client>> = OnceCell::new();
@@ -39,7 +45,7 @@ impl Db {
         filter: impl Into<Option<Document>>,
         options: impl Into<Option<FindOptions>>,
         call_back: &dyn Fn(T),
-    ) -> Result<(), Error>
+    ) -> Result<(), AppError>
     where
         T: DeserializeOwned,
     {
@@ -75,15 +81,15 @@ impl Db {
         table: &str,
         filter: impl Into<Option<Document>>,
         options: impl Into<Option<FindOneOptions>>,
-    ) -> Result<Option<Document>, Error> {
+    ) -> Result<Option<Document>, AppError> {
         let client = Db::get_instance();
         let db = client.database(TABLE_NAME);
         let collection = db.collection(table);
 
-        collection.find_one(filter, options).await
+        Ok(collection.find_one(filter, options).await?)
     }
 
-    pub async fn save(table: &str, filter: Document, app: Document) -> Result<(), Error> {
+    pub async fn save(table: &str, filter: Document, app: Document) -> Result<(), AppError> {
         let client = Db::get_instance();
         let db = client.database(TABLE_NAME);
         let collection = db.collection(table);
@@ -107,7 +113,23 @@ impl Db {
         Ok(())
     }
 
-    pub async fn delete(table: &str, filter: Document) -> Result<(), Error> {
+    /// 只更新 set 中的字段, 不覆盖其他字段
+    pub async fn update(table: &str, filter: Document, set: Document) -> Result<(), AppError> {
+        let client = Db::get_instance();
+        let db = client.database(TABLE_NAME);
+        let collection = db.collection(table);
+
+        let mut set = set;
+        set.insert(KEY_UPDATE_TIME, Bson::DateTime(chrono::Utc::now()));
+
+        collection
+            .update_one(filter, bson::doc! {"$set": set}, None)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(table: &str, filter: Document) -> Result<(), AppError> {
         let client = Db::get_instance();
         let db = client.database(TABLE_NAME);
         let collection = db.collection(table);
@@ -119,6 +141,34 @@ impl Db {
         Ok(())
     }
 
+    pub async fn count(table: &str, filter: impl Into<Option<Document>>) -> Result<i64, AppError> {
+        let client = Db::get_instance();
+        let db = client.database(TABLE_NAME);
+        let collection = db.collection(table);
+
+        let count = collection.count_documents(filter, None).await?;
+
+        Ok(count as i64)
+    }
+
+    pub async fn aggregate(
+        table: &str,
+        pipeline: Vec<Document>,
+    ) -> Result<Vec<Document>, AppError> {
+        let client = Db::get_instance();
+        let db = client.database(TABLE_NAME);
+        let collection = db.collection(table);
+
+        let mut cursor = collection.aggregate(pipeline, None).await?;
+
+        let mut vec = Vec::new();
+        while let Some(result) = cursor.next().await {
+            vec.push(result?);
+        }
+
+        Ok(vec)
+    }
+
     pub async fn contians(table: &str, filter: Document) -> bool {
         let client = Db::get_instance();
         let db = client.database(TABLE_NAME);
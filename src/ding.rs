@@ -5,12 +5,12 @@ use log::info;
 use once_cell::sync::OnceCell;
 use serde_json::json;
 
-use crate::{build_params::AppParams, get_default, get_upload_url, result_err};
+use crate::{build_params::AppParams, error::AppError, get_default, get_upload_url, result_err};
 
 static MOBILE_MAP: OnceCell<HashMap<&str, &str>> = OnceCell::new();
 const DING_URL:&'static str = "https://oapi.dingtalk.com/robot/send?access_token=bf650de5c1ab6d8c05edcd826db6c0808dcfa0f673d217de466240652643ad3f";
 
-async fn _ding(title: &str, content: &str) -> Result<(), String> {
+async fn _ding(title: &str, content: &str) -> Result<(), AppError> {
     info!("start ding with {}", title);
 
     let client = reqwest::Client::new();
@@ -30,14 +30,14 @@ async fn _ding(title: &str, content: &str) -> Result<(), String> {
         .json(&body)
         .send()
         .await
-        .map_err(result_err!())?;
+        .map_err(result_err!(AppError::Notification))?;
 
     info!("ding response : {:?}", res.text().await);
 
     Ok(())
 }
 
-pub async fn post_ding(app: &AppParams) -> Result<(), String> {
+pub async fn post_ding(app: &AppParams) -> Result<(), AppError> {
     let email = app.params.email.clone();
     if email.is_some() {
         let id = app.build_id;
@@ -136,7 +136,7 @@ mod tests {
                     let result = bson::from_bson::<AppParams>(Bson::Document(doc));
                     match result {
                         Ok(app) => {
-                            return super::post_ding(&app).await;
+                            return super::post_ding(&app).await.map_err(|e| e.to_string());
                         }
                         Err(err) => {
                             info!("{}", err);
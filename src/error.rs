@@ -0,0 +1,184 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+
+use crate::http_response::response_error;
+
+#[derive(Debug, PartialEq)]
+pub enum AppError {
+    /// 请求参数错误
+    Validation(String),
+    Unauthorized(String),
+    Forbidden(String),
+    NotFound(String),
+    /// 与已有数据冲突
+    Conflict(String),
+    /// 超出限流, 第二个参数为建议的重试秒数
+    RateLimited(String, u64),
+    /// 代码下载
+    Scm(String),
+    /// 编译
+    Gradle(String),
+    /// 编译产物和打包参数不一致
+    Verify(String),
+    /// 文件服务器上传下载
+    Upload(String),
+    /// 数据库
+    Storage(String),
+    /// 邮件, 钉钉等通知
+    Notification(String),
+    /// 用户取消了任务, 不算打包失败
+    Cancelled(String),
+    Internal(String),
+}
+
+impl AppError {
+    /// 稳定的错误码, 供调用方判断
+    pub fn code(&self) -> &'static str {
+        match self {
+            AppError::Validation(_) => "validation_error",
+            AppError::Unauthorized(_) => "unauthorized",
+            AppError::Forbidden(_) => "forbidden",
+            AppError::NotFound(_) => "not_found",
+            AppError::Conflict(_) => "conflict",
+            AppError::RateLimited(_, _) => "rate_limited",
+            AppError::Scm(_) => "scm_error",
+            AppError::Gradle(_) => "gradle_error",
+            AppError::Verify(_) => "verify_error",
+            AppError::Upload(_) => "upload_error",
+            AppError::Storage(_) => "storage_error",
+            AppError::Notification(_) => "notification_error",
+            AppError::Cancelled(_) => "cancelled",
+            AppError::Internal(_) => "internal_error",
+        }
+    }
+
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            AppError::RateLimited(_, secs) => Some(*secs),
+            _ => None,
+        }
+    }
+
+    pub fn msg(&self) -> &str {
+        match self {
+            AppError::Validation(s)
+            | AppError::Unauthorized(s)
+            | AppError::Forbidden(s)
+            | AppError::NotFound(s)
+            | AppError::Conflict(s)
+            | AppError::RateLimited(s, _)
+            | AppError::Scm(s)
+            | AppError::Gradle(s)
+            | AppError::Verify(s)
+            | AppError::Upload(s)
+            | AppError::Storage(s)
+            | AppError::Notification(s)
+            | AppError::Cancelled(s)
+            | AppError::Internal(s) => s,
+        }
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.msg())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+impl ResponseError for AppError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            AppError::Validation(_) => StatusCode::BAD_REQUEST,
+            AppError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            AppError::Forbidden(_) => StatusCode::FORBIDDEN,
+            AppError::NotFound(_) => StatusCode::NOT_FOUND,
+            AppError::Conflict(_) | AppError::Cancelled(_) => StatusCode::CONFLICT,
+            AppError::RateLimited(_, _) => StatusCode::TOO_MANY_REQUESTS,
+            AppError::Scm(_) | AppError::Upload(_) | AppError::Notification(_) => {
+                StatusCode::BAD_GATEWAY
+            }
+            AppError::Storage(_) => StatusCode::SERVICE_UNAVAILABLE,
+            AppError::Gradle(_) | AppError::Verify(_) | AppError::Internal(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        response_error(self)
+    }
+}
+
+impl From<mongodb::error::Error> for AppError {
+    fn from(err: mongodb::error::Error) -> Self {
+        AppError::Storage(format!("db error : {}", err))
+    }
+}
+
+impl From<bson::ser::Error> for AppError {
+    fn from(err: bson::ser::Error) -> Self {
+        AppError::Internal(format!("to_bson error : {}", err))
+    }
+}
+
+impl From<bson::de::Error> for AppError {
+    fn from(err: bson::de::Error) -> Self {
+        AppError::Storage(format!("from_bson error : {}", err))
+    }
+}
+
+impl From<std::io::Error> for AppError {
+    fn from(err: std::io::Error) -> Self {
+        AppError::Internal(format!("io error : {}", err))
+    }
+}
+
+impl From<quick_xml::Error> for AppError {
+    fn from(err: quick_xml::Error) -> Self {
+        AppError::Internal(format!("xml error : {:?}", err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::{http::StatusCode, ResponseError};
+
+    use super::AppError;
+
+    #[test]
+    fn test_error_status() {
+        let err = AppError::Validation("bad".to_string());
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert_eq!(err.code(), "validation_error");
+        assert_eq!(err.to_string(), "bad");
+
+        assert_eq!(
+            AppError::NotFound(String::new()).status_code(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            AppError::Conflict(String::new()).status_code(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(
+            AppError::Storage(String::new()).status_code(),
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(AppError::Verify(String::new()).code(), "verify_error");
+        assert_eq!(AppError::Cancelled(String::new()).code(), "cancelled");
+    }
+
+    #[test]
+    fn test_error_response() {
+        let res = AppError::Forbidden("permission denied".to_string()).error_response();
+        assert_eq!(res.status(), StatusCode::FORBIDDEN);
+        assert!(res.headers().get("Retry-After").is_none());
+
+        let res = AppError::RateLimited("too many builds".to_string(), 30).error_response();
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(res.headers().get("Retry-After").unwrap(), "30");
+    }
+}
@@ -1,36 +1,64 @@
 use crate::build_params::AppParams;
+use crate::error::AppError;
 use crate::work::*;
 use async_trait::async_trait;
 
 #[async_trait]
 pub trait BuildStep {
-    fn step_source(&self, app: &AppParams) -> Result<(), String> {
+    fn step_source(&self, app: &AppParams) -> Result<(), AppError> {
         fetch_source(app)
     }
 
-    async fn step_change(&self, app: &AppParams) -> Result<(), String> {
-        change_config(app)
+    async fn step_change(&self, app: &AppParams) -> Result<(), AppError> {
+        change_config(app)?;
+        change_icon(app).await
     }
 
-    fn step_build(&self, app: &AppParams) -> Result<(), String> {
+    fn step_build(&self, app: &AppParams) -> Result<(), AppError> {
         release_build(app)
     }
 
-    async fn step_upload(&self, app: &mut AppParams) -> Result<(), String> {
+    fn step_obfuscate(&self, app: &AppParams) -> Result<(), AppError> {
+        obfuscate_build(app)
+    }
+
+    async fn step_sign(&self, app: &mut AppParams) -> Result<(), AppError> {
+        sign_build(app).await
+    }
+
+    fn step_verify(&self, app: &mut AppParams) -> Result<(), AppError> {
+        verify_build(app)
+    }
+
+    async fn step_upload(&self, app: &mut AppParams) -> Result<(), AppError> {
         upload_build(app).await
     }
 
-    async fn step(&self, app: &mut AppParams) -> Result<(), String> {
+    async fn step(&self, app: &mut AppParams) -> Result<(), AppError> {
         // 1. 下载代码
         self.step_source(app)?;
+        check_cancelled()?;
 
         // 2. 修改配置
         self.step_change(app).await?;
+        check_cancelled()?;
 
         // 3. 开始打包
         self.step_build(app)?;
+        check_cancelled()?;
+
+        // 4. 混淆
+        self.step_obfuscate(app)?;
+        check_cancelled()?;
+
+        // 5. 签名
+        self.step_sign(app).await?;
+        check_cancelled()?;
+
+        // 6. 检查 apk
+        self.step_verify(app)?;
 
-        // 4. 结果上传
+        // 7. 结果上传
         self.step_upload(app).await?;
 
         Ok(())
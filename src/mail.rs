@@ -1,7 +1,7 @@
 use bson::Bson;
 use chrono::Local;
 
-use crate::result_err;
+use crate::{error::AppError, result_err};
 use log::info;
 use regex::Regex;
 use serde_json::json;
@@ -13,7 +13,7 @@ use crate::{
     http::QueryResponse,
 };
 
-async fn _email(mail: &str, title: &str, content: &str) -> Result<(), String> {
+async fn _email(mail: &str, title: &str, content: &str) -> Result<(), AppError> {
     info!(" start send email to {}", mail);
 
     let client = reqwest::Client::new();
@@ -27,7 +27,7 @@ async fn _email(mail: &str, title: &str, content: &str) -> Result<(), String> {
         }))
         .send()
         .await
-        .map_err(result_err!())?;
+        .map_err(result_err!(AppError::Notification))?;
 
     Ok(())
 }
@@ -52,7 +52,7 @@ async fn send_response(app: &AppParams) {
     }
 }
 
-pub async fn send_email(app: &AppParams) -> Result<(), String> {
+pub async fn send_email(app: &AppParams) -> Result<(), AppError> {
     send_response(app).await;
 
     if crate::config::Config::enable_ding() {
@@ -143,11 +143,9 @@ pub async fn send_email(app: &AppParams) -> Result<(), String> {
     Ok(())
 }
 
-pub async fn send_email_by_id(id: &str) -> Result<(), String> {
+pub async fn send_email_by_id(id: &str) -> Result<(), AppError> {
     if Db::contians(COLLECTION_BUILD, filter_build_id!(id)).await {
-        let result = Db::find_one(COLLECTION_BUILD, filter_build_id!(id), None)
-            .await
-            .unwrap();
+        let result = Db::find_one(COLLECTION_BUILD, filter_build_id!(id), None).await?;
 
         match result {
             Some(doc) => {
@@ -156,14 +154,16 @@ pub async fn send_email_by_id(id: &str) -> Result<(), String> {
                     Ok(app) => send_email(&app).await,
                     Err(err) => {
                         info!("{}", err);
-                        Err(format!("{:?}", err))
+                        Err(err.into())
                     }
                 }
             }
-            None => Err(format!("not found this document !!!")),
+            None => Err(AppError::NotFound(
+                "not found this document !!!".to_string(),
+            )),
         }
     } else {
-        Err(format!("not found this build id!!!"))
+        Err(AppError::NotFound("not found this build id!!!".to_string()))
     }
 }
 
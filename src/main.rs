@@ -15,31 +15,47 @@ use actix_web::{
 };
 use args::Opt;
 use bson::doc;
-use build_params::{AppParams, CODE_BUILDING, CODE_WAITING};
+use build_params::{AppParams, CODE_BUILDING};
 use chrono::{DateTime, NaiveDateTime, Utc};
 use db::{Db, COLLECTION_BUILD};
+use error::AppError;
 use http_response::*;
-use log::info;
+use log::{info, warn};
 use mongodb::options::FindOptions;
 use serde_json::Value;
 use tokio::time::interval;
 
 use structopt::StructOpt;
 
+mod archive;
 mod args;
+mod artifact;
+mod auth;
+mod axml;
+mod build_log;
 mod build_params;
 mod config;
 mod db;
 mod ding;
+mod error;
 mod framework;
+mod hook;
 mod http;
 mod http_response;
+mod icon;
+mod keystore;
+mod limit;
 mod mail;
+mod preset;
+mod queue;
 mod redis;
+mod schedule;
 mod shell;
+mod stats;
 mod utils;
 mod weed;
 mod work;
+mod worker;
 
 #[post("/test/post")]
 async fn hello(req_body: String) -> impl Responder {
@@ -49,7 +65,7 @@ async fn hello(req_body: String) -> impl Responder {
 
 fn post_error(err: JsonPayloadError, _: &HttpRequest) -> Error {
     let res = format!("{}", err);
-    InternalError::from_response(err, response_error(res)).into()
+    InternalError::from_response(err, response_error(&AppError::Validation(res))).into()
 }
 
 fn clear_cache() -> io::Result<()> {
@@ -92,7 +108,7 @@ fn clear_cache() -> io::Result<()> {
     Ok(())
 }
 
-async fn time_work(manager: bool) {
+async fn time_work(manager: bool, builder: bool) {
     tokio::time::sleep(Duration::from_millis(1000)).await;
     info!("time_work start ...");
 
@@ -104,11 +120,43 @@ async fn time_work(manager: bool) {
             info!(" clear cache error = {}", err);
         }
 
+        if builder {
+            // 登记打包服务状态
+            worker::Worker::heartbeat().await;
+        }
+
         if !manager {
             continue;
         }
 
-        let filter = doc! {"code":{"$gt": 1}};
+        // 定时打包
+        schedule::run_schedules().await;
+
+        // 按优先级分发等待中的任务, 每次只分发空闲打包服务数量的任务
+        match queue::waiting_builds().await {
+            Ok(list) => {
+                let idle = worker::Worker::list()
+                    .await
+                    .live
+                    .iter()
+                    .filter(|w| !w.building)
+                    .count();
+
+                for app in list.iter().take(idle.max(1)) {
+                    info!(
+                        "found waiting work id = {}, priority = {:?} ",
+                        app.build_id, app.params.priority
+                    );
+
+                    Redis::publish(BUILD_CHANNEL, &app.build_id.to_string()).await;
+                }
+            }
+            Err(err) => {
+                info!("find waiting error : {}", err);
+            }
+        }
+
+        let filter = doc! {"code": CODE_BUILDING};
 
         let find_options = FindOptions::builder()
             .sort(doc! { "date": -1 })
@@ -126,26 +174,13 @@ async fn time_work(manager: bool) {
             info!("find error : {:?}", result.err());
         } else {
             for app in vec.lock().unwrap().iter() {
-                if app.status.code == CODE_WAITING {
-                    info!("found waiting work id = {} ", app.build_id);
+                let time = app.update_time.unwrap_or(app.date);
 
-                    Redis::publish(BUILD_CHANNEL, &app.build_id.to_string()).await;
+                let duration = time.signed_duration_since(chrono::Utc::now());
 
-                    continue;
-                } else if app.status.code == CODE_BUILDING {
-                    let time = if app.update_time.is_none() {
-                        app.date
-                    } else {
-                        app.update_time.unwrap()
-                    };
-
-                    let duration = time.signed_duration_since(chrono::Utc::now());
-
-                    if duration.num_minutes().abs() > 20 {
-                        info!(" exception building dur = {} ", duration);
-                        Redis::publish(BUILD_CHANNEL, &app.build_id.to_string()).await;
-                        continue;
-                    }
+                if duration.num_minutes().abs() > 20 {
+                    info!(" exception building dur = {} ", duration);
+                    Redis::publish(BUILD_CHANNEL, &app.build_id.to_string()).await;
                 }
             }
         }
@@ -168,6 +203,11 @@ async fn main() -> std::io::Result<()> {
         opt.ip = whoami::hostname();
     }
 
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_addr(&format!("{}:{}", opt.ip, opt.port));
+
     opt.ip = format!("{}-{}", opt.ip, VERSION);
 
     config::Config::get_instance()
@@ -185,6 +225,41 @@ async fn main() -> std::io::Result<()> {
         .unwrap()
         .set_no_upload(opt.no_upload);
 
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_admin_token(&opt.admin_token);
+
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_hook_secret(&opt.hook_secret);
+
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_keystore_secret(&opt.keystore_secret);
+
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_worker_token(&opt.worker_token);
+
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_idempotency_window(opt.idempotency_window);
+
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_rate_limit(opt.rate_limit, opt.rate_window);
+
+    config::Config::get_instance()
+        .lock()
+        .unwrap()
+        .set_max_waiting(opt.max_waiting);
+
     if !opt.cache_path.is_empty() {
         config::Config::get_instance()
             .lock()
@@ -230,7 +305,7 @@ async fn main() -> std::io::Result<()> {
     thread::spawn(move || {
         let rt = tokio::runtime::Runtime::new().unwrap();
         rt.block_on(async move {
-            time_work(is_manager).await;
+            time_work(is_manager, !is_manager || is_manager_build).await;
         })
     });
 
@@ -245,21 +320,108 @@ async fn main() -> std::io::Result<()> {
             VERSION
         );
 
+        if opt.admin_token.is_empty() {
+            warn!("admin token is empty, api auth disabled !!");
+        }
+
         HttpServer::new(|| {
             App::new()
+                .wrap(auth::Auth)
                 .wrap(Logger::new("%U %s %D"))
                 .service(hello)
+                .route("/", web::get().to(http::MyRoute::index))
                 .service(
                     web::resource("/app/build")
                         .data(web::JsonConfig::default().error_handler(post_error))
                         .route(web::post().to(http::MyRoute::build)),
                 )
+                .service(
+                    web::resource("/app/build/matrix")
+                        .data(web::JsonConfig::default().error_handler(post_error))
+                        .route(web::post().to(http::MyRoute::matrix)),
+                )
+                .route("/app/build/{id}", web::delete().to(http::MyRoute::cancel))
+                .service(
+                    web::resource("/app/build/{id}")
+                        .data(web::JsonConfig::default().error_handler(post_error))
+                        .route(web::patch().to(http::MyRoute::update_build)),
+                )
+                .route(
+                    "/app/build/{id}/rebuild",
+                    web::post().to(http::MyRoute::rebuild),
+                )
                 .route("/app/query/{id}", web::get().to(http::MyRoute::query))
                 .route("/app/query", web::get().to(http::MyRoute::querys))
+                .route("/app/group/{id}", web::get().to(http::MyRoute::group))
+                .route("/app/workers", web::get().to(http::MyRoute::workers))
+                .route("/app/stats", web::get().to(http::MyRoute::stats))
+                .route("/app/log/{id}", web::get().to(http::MyRoute::log))
                 .route(
                     "/app/package/{id}.apk",
                     web::get().to(http::MyRoute::package),
                 )
+                .route(
+                    "/app/package/{id}/{name}",
+                    web::get().to(http::MyRoute::artifact),
+                )
+                .route("/app/token", web::get().to(http::MyRoute::tokens))
+                .route("/app/token", web::post().to(http::MyRoute::create_token))
+                .route(
+                    "/app/token/{name}",
+                    web::delete().to(http::MyRoute::delete_token),
+                )
+                .route("/app/trigger", web::get().to(http::MyRoute::triggers))
+                .service(
+                    web::resource("/app/trigger")
+                        .data(web::JsonConfig::default().error_handler(post_error))
+                        .route(web::post().to(http::MyRoute::save_trigger)),
+                )
+                .route(
+                    "/app/trigger/{name}",
+                    web::delete().to(http::MyRoute::delete_trigger),
+                )
+                .route("/app/preset", web::get().to(http::MyRoute::presets))
+                .service(
+                    web::resource("/app/preset")
+                        .data(web::JsonConfig::default().error_handler(post_error))
+                        .route(web::post().to(http::MyRoute::save_preset)),
+                )
+                .route("/app/preset/{name}", web::get().to(http::MyRoute::preset))
+                .route(
+                    "/app/preset/{name}",
+                    web::delete().to(http::MyRoute::delete_preset),
+                )
+                .route(
+                    "/app/preset/{name}/build",
+                    web::post().to(http::MyRoute::preset_build),
+                )
+                .route("/app/keystore", web::get().to(http::MyRoute::keystores))
+                .service(
+                    web::resource("/app/keystore")
+                        .data(
+                            web::JsonConfig::default()
+                                .limit(1 << 20)
+                                .error_handler(post_error),
+                        )
+                        .route(web::post().to(http::MyRoute::save_keystore)),
+                )
+                .route(
+                    "/app/keystore/{name}",
+                    web::delete().to(http::MyRoute::delete_keystore),
+                )
+                .route("/app/schedule", web::get().to(http::MyRoute::schedules))
+                .service(
+                    web::resource("/app/schedule")
+                        .data(web::JsonConfig::default().error_handler(post_error))
+                        .route(web::post().to(http::MyRoute::save_schedule)),
+                )
+                .route(
+                    "/app/schedule/{name}",
+                    web::delete().to(http::MyRoute::delete_schedule),
+                )
+                .route("/hooks/gitlab", web::post().to(http::MyRoute::gitlab_hook))
+                .route("/hooks/gitea", web::post().to(http::MyRoute::gitea_hook))
+                .route("/worker/log/{id}", web::get().to(http::MyRoute::worker_log))
         })
         .bind(format!("0.0.0.0:{}", opt.port))?
         .run()
@@ -274,10 +436,20 @@ async fn main() -> std::io::Result<()> {
 "#,
             VERSION
         );
-        HttpServer::new(|| App::new().wrap(Logger::new("%U %s %D")).service(hello))
-            .workers(1)
-            .bind(format!("0.0.0.0:{}", opt.port))?
-            .run()
-            .await
+
+        if opt.worker_token.is_empty() {
+            warn!("worker token is empty, /worker/log auth disabled !!");
+        }
+
+        HttpServer::new(|| {
+            App::new()
+                .wrap(Logger::new("%U %s %D"))
+                .service(hello)
+                .route("/worker/log/{id}", web::get().to(http::MyRoute::worker_log))
+        })
+        .workers(1)
+        .bind(format!("0.0.0.0:{}", opt.port))?
+        .run()
+        .await
     }
 }
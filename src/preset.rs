@@ -0,0 +1,84 @@
+use std::sync::{Arc, Mutex};
+
+use bson::{doc, Bson, DateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build_params::BuildParams,
+    db::{Db, COLLECTION_PRESET},
+    error::AppError,
+};
+
+/// 命名的打包参数
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Preset {
+    pub name: String,
+    pub params: BuildParams,
+    /// 每次修改加 1
+    #[serde(default)]
+    pub revision: i32,
+    #[serde(default = "default_date")]
+    pub date: DateTime,
+}
+
+/// 打包任务使用的预设版本
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct PresetRef {
+    pub name: String,
+    pub revision: i32,
+}
+
+fn default_date() -> DateTime {
+    DateTime(chrono::Utc::now())
+}
+
+impl Preset {
+    pub fn to_ref(&self) -> PresetRef {
+        PresetRef {
+            name: self.name.clone(),
+            revision: self.revision,
+        }
+    }
+
+    pub async fn find(name: &str) -> Result<Preset, AppError> {
+        let result = Db::find_one(COLLECTION_PRESET, doc! {"name": name}, None).await?;
+
+        match result {
+            Some(doc) => Ok(bson::from_bson::<Preset>(Bson::Document(doc))?),
+            None => Err(AppError::NotFound(format!("preset {} not found", name))),
+        }
+    }
+
+    pub async fn list() -> Result<Vec<Preset>, AppError> {
+        let vec: Arc<Mutex<Vec<Preset>>> = Arc::new(Mutex::new(Vec::new()));
+
+        Db::find(COLLECTION_PRESET, doc! {}, None, &|preset| {
+            vec.lock().unwrap().push(preset)
+        })
+        .await?;
+
+        let mut list = vec.lock().unwrap().drain(..).collect::<Vec<Preset>>();
+        list.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(list)
+    }
+
+    /// 保存预设, 已存在时 revision 加 1
+    pub async fn save(mut self) -> Result<Preset, AppError> {
+        if self.name.is_empty() {
+            return Err(AppError::Validation("preset name is empty".to_string()));
+        }
+        self.params.validate()?;
+
+        self.revision = match Preset::find(&self.name).await {
+            Ok(old) => old.revision + 1,
+            Err(AppError::NotFound(_)) => 1,
+            Err(e) => return Err(e),
+        };
+        self.date = DateTime(chrono::Utc::now());
+
+        let doc = bson::to_bson(&self)?.as_document().unwrap().clone();
+        Db::save(COLLECTION_PRESET, doc! {"name": self.name.clone()}, doc).await?;
+
+        Ok(self)
+    }
+}
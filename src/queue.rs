@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use bson::doc;
+use chrono::{DateTime, Duration, Utc};
+use mongodb::options::FindOptions;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    build_params::{AppParams, BuildParams, Version, CODE_BUILDING, CODE_WAITING},
+    db::{Db, COLLECTION_BUILD},
+    error::AppError,
+    worker::Worker,
+};
+
+/// 没有历史记录时的默认编译时间, 单位秒
+const DEFAULT_BUILD_TIME: i64 = 60 * 5;
+/// 取最近多少次成功的编译时间计算平均值
+const HISTORY_SIZE: i64 = 20;
+/// 每等待多少分钟优先级加 1, 避免低优先级的任务一直等待
+const AGING_MINUTES: i64 = 10;
+/// 参与排序的等待任务上限
+const MAX_WAITING: i64 = 500;
+
+/// 等待中任务的排队信息
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct QueueInfo {
+    /// 在等待队列中的位置, 从 1 开始
+    pub position: i64,
+    /// 在线的打包服务数量
+    pub workers: i64,
+    /// 加上等待时间后的优先级
+    pub priority: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_start: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub estimated_finish: Option<String>,
+}
+
+/// 前面还有 ahead 个任务, 每轮可以同时编译 workers 个
+fn estimate(
+    ahead: i64,
+    workers: i64,
+    build_time: i64,
+    now: DateTime<Utc>,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    if workers <= 0 {
+        return None;
+    }
+
+    let start = now + Duration::seconds(ahead / workers * build_time);
+    Some((start, start + Duration::seconds(build_time)))
+}
+
+/// 优先级加上等待时间的补偿
+pub fn effective_priority(priority: Option<i32>, date: DateTime<Utc>, now: DateTime<Utc>) -> i64 {
+    priority.unwrap_or(0) as i64 + (now - date).num_minutes().max(0) / AGING_MINUTES
+}
+
+/// 排序用的 key, 优先级高的在前, 相同时先提交的在前
+fn rank(priority: Option<i32>, date: DateTime<Utc>, now: DateTime<Utc>) -> (i64, i64) {
+    (
+        -effective_priority(priority, date, now),
+        date.timestamp_millis(),
+    )
+}
+
+/// 等待中的任务, 按分发顺序排列
+pub async fn waiting_builds() -> Result<Vec<AppParams>, AppError> {
+    let find_options = FindOptions::builder()
+        .sort(doc! { "date": 1 })
+        .limit(Some(MAX_WAITING))
+        .build();
+
+    let vec: Arc<Mutex<Vec<AppParams>>> = Arc::new(Mutex::new(Vec::new()));
+
+    Db::find(
+        COLLECTION_BUILD,
+        doc! {"code": CODE_WAITING},
+        find_options,
+        &|app| vec.lock().unwrap().push(app),
+    )
+    .await?;
+
+    let now = Utc::now();
+    let mut list = vec.lock().unwrap().drain(..).collect::<Vec<AppParams>>();
+    list.sort_by_key(|app| rank(app.params.priority, app.date.0, now));
+
+    Ok(list)
+}
+
+/// 同项目同渠道最近成功编译的平均耗时
+async fn average_build_time(version: &Version) -> Result<i64, AppError> {
+    let mut filter = doc! {"code": 0, "build_time": {"$gt": 0}};
+    if let Some(ref project) = version.project_name {
+        filter.insert("params.version.project_name", project.clone());
+    }
+    if let Some(ref channel) = version.channel {
+        filter.insert("params.version.channel", channel.clone());
+    }
+
+    let find_options = FindOptions::builder()
+        .sort(doc! { "date": -1 })
+        .limit(Some(HISTORY_SIZE))
+        .build();
+
+    let vec: Arc<Mutex<Vec<i64>>> = Arc::new(Mutex::new(Vec::new()));
+
+    Db::find(COLLECTION_BUILD, filter, find_options, &|app: AppParams| {
+        vec.lock().unwrap().push(app.build_time as i64)
+    })
+    .await?;
+
+    let times = vec.lock().unwrap();
+    if times.is_empty() {
+        return Ok(DEFAULT_BUILD_TIME);
+    }
+
+    Ok(times.iter().sum::<i64>() / times.len() as i64)
+}
+
+/// 一次请求内共用的排队数据, 列表中的多个任务不再重复查询
+pub struct QueueSnapshot {
+    now: DateTime<Utc>,
+    /// 等待中任务的排序 key
+    ranks: Vec<(i64, i64)>,
+    building: i64,
+    workers: i64,
+    /// 按项目和渠道缓存的平均编译时间
+    build_times: HashMap<(Option<String>, Option<String>), i64>,
+}
+
+impl QueueSnapshot {
+    pub async fn load() -> Result<Self, AppError> {
+        let now = Utc::now();
+        let ranks = waiting_builds()
+            .await?
+            .iter()
+            .map(|app| rank(app.params.priority, app.date.0, now))
+            .collect();
+        let building = Db::count(COLLECTION_BUILD, doc! {"code": CODE_BUILDING}).await?;
+        let workers = Worker::list().await.live.len() as i64;
+
+        Ok(QueueSnapshot {
+            now,
+            ranks,
+            building,
+            workers,
+            build_times: HashMap::new(),
+        })
+    }
+
+    pub async fn info(
+        &mut self,
+        code: i32,
+        date: &bson::DateTime,
+        params: &BuildParams,
+    ) -> Result<Option<QueueInfo>, AppError> {
+        if code != CODE_WAITING {
+            return Ok(None);
+        }
+
+        let key = rank(params.priority, date.0, self.now);
+        let before = self.ranks.iter().filter(|r| **r < key).count() as i64;
+
+        let version = &params.version;
+        let cache_key = (version.project_name.clone(), version.channel.clone());
+        let build_time = match self.build_times.get(&cache_key) {
+            Some(t) => *t,
+            None => {
+                let t = average_build_time(version).await?;
+                self.build_times.insert(cache_key, t);
+                t
+            }
+        };
+
+        let (start, finish) =
+            match estimate(before + self.building, self.workers, build_time, self.now) {
+                Some((s, f)) => (Some(s.to_rfc3339()), Some(f.to_rfc3339())),
+                None => (None, None),
+            };
+
+        Ok(Some(QueueInfo {
+            position: before + 1,
+            workers: self.workers,
+            priority: effective_priority(params.priority, date.0, self.now),
+            estimated_start: start,
+            estimated_finish: finish,
+        }))
+    }
+}
+
+pub async fn queue_info(
+    code: i32,
+    date: &bson::DateTime,
+    params: &BuildParams,
+) -> Result<Option<QueueInfo>, AppError> {
+    if code != CODE_WAITING {
+        return Ok(None);
+    }
+
+    QueueSnapshot::load().await?.info(code, date, params).await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::{effective_priority, estimate};
+
+    #[test]
+    fn test_estimate() {
+        let now = Utc.ymd(2021, 2, 1).and_hms(10, 0, 0);
+
+        let (start, finish) = estimate(0, 2, 300, now).unwrap();
+        assert_eq!(start, now);
+        assert_eq!(finish, now + Duration::seconds(300));
+
+        let (start, _) = estimate(5, 2, 300, now).unwrap();
+        assert_eq!(start, now + Duration::seconds(600));
+
+        assert!(estimate(1, 0, 300, now).is_none());
+    }
+
+    #[test]
+    fn test_effective_priority() {
+        let now = Utc.ymd(2021, 2, 1).and_hms(10, 0, 0);
+
+        assert_eq!(effective_priority(None, now, now), 0);
+        assert_eq!(effective_priority(Some(5), now, now), 5);
+        assert_eq!(
+            effective_priority(None, now - Duration::minutes(35), now),
+            3
+        );
+        assert_eq!(
+            effective_priority(Some(-2), now - Duration::minutes(20), now),
+            0
+        );
+    }
+}
@@ -1,4 +1,4 @@
-use std::{sync::Arc, thread, time::Duration};
+use std::{collections::HashMap, sync::Arc, thread, time::Duration};
 
 use log::{info, warn};
 use once_cell::sync::OnceCell;
@@ -13,8 +13,22 @@ pub struct Redis {
 
 static RM: OnceCell<Arc<Redis>> = OnceCell::new();
 pub const BUILD_CHANNEL: &'static str = "build_work";
+pub const CANCEL_CHANNEL: &str = "build_cancel";
+pub const WORKERS: &str = "build_workers";
 const EXPIRE_TIME: i32 = 60 * 12;
 
+/// 未超过上限才计数, 第一次计数时设置过期时间
+const INCR_SCRIPT: &str = r"
+local value = tonumber(redis.call('get', KEYS[1]) or '0') + tonumber(ARGV[1])
+if value <= tonumber(ARGV[2]) then
+    redis.call('incrby', KEYS[1], ARGV[1])
+    if redis.call('ttl', KEYS[1]) < 0 then
+        redis.call('expire', KEYS[1], ARGV[3])
+    end
+end
+return {value, redis.call('ttl', KEYS[1])}
+";
+
 impl Redis {
     fn get_instance() -> Option<Arc<Redis>> {
         let result = RM.get();
@@ -48,6 +62,202 @@ impl Redis {
         }
     }
 
+    pub async fn set_with_time(key: &str, value: &str, time: i32) -> bool {
+        let result = Redis::get_instance();
+
+        match result {
+            Some(res) => {
+                let mut con = res.con.clone();
+
+                let result: RedisResult<()> = redis::cmd("set")
+                    .arg(key)
+                    .arg(value)
+                    .arg("EX")
+                    .arg(time)
+                    .query_async(&mut con)
+                    .await;
+
+                if result.is_err() {
+                    info!("set error = {:?}", result.err());
+                    return false;
+                }
+                return true;
+            }
+            None => {
+                info!("set error, redis not ready...");
+            }
+        }
+        false
+    }
+
+    pub async fn get(key: &str) -> Option<String> {
+        let result = Redis::get_instance();
+
+        match result {
+            Some(res) => {
+                let mut con = res.con.clone();
+
+                let result: RedisResult<Option<String>> =
+                    redis::cmd("get").arg(key).query_async(&mut con).await;
+
+                match result {
+                    Ok(value) => value,
+                    Err(err) => {
+                        info!("get error = {:?}", err);
+                        None
+                    }
+                }
+            }
+            None => {
+                info!("get error, redis not ready...");
+                None
+            }
+        }
+    }
+
+    /// 计数加 count, 超过 limit 时不计数, 返回计数后的值和剩余秒数
+    /// 用 lua 脚本保证计数和设置过期时间是原子操作
+    pub async fn incr_with_time(
+        key: &str,
+        count: i64,
+        limit: i64,
+        time: i32,
+    ) -> Option<(i64, i64)> {
+        let result = Redis::get_instance();
+
+        match result {
+            Some(res) => {
+                let mut con = res.con.clone();
+
+                let result: RedisResult<(i64, i64)> = redis::Script::new(INCR_SCRIPT)
+                    .key(key)
+                    .arg(count)
+                    .arg(limit)
+                    .arg(time)
+                    .invoke_async(&mut con)
+                    .await;
+
+                match result {
+                    Ok(v) => Some(v),
+                    Err(err) => {
+                        info!("incr error = {:?}", err);
+                        None
+                    }
+                }
+            }
+            None => {
+                info!("incr error, redis not ready...");
+                None
+            }
+        }
+    }
+
+    pub async fn hset(key: &str, field: &str, value: &str) -> bool {
+        let result = Redis::get_instance();
+
+        match result {
+            Some(res) => {
+                let mut con = res.con.clone();
+
+                let result: RedisResult<()> = redis::cmd("hset")
+                    .arg(key)
+                    .arg(field)
+                    .arg(value)
+                    .query_async(&mut con)
+                    .await;
+
+                if result.is_err() {
+                    info!("hset error = {:?}", result.err());
+                    return false;
+                }
+                return true;
+            }
+            None => {
+                info!("hset error, redis not ready...");
+            }
+        }
+        false
+    }
+
+    pub async fn hget(key: &str, field: &str) -> Option<String> {
+        let result = Redis::get_instance();
+
+        match result {
+            Some(res) => {
+                let mut con = res.con.clone();
+
+                let result: RedisResult<Option<String>> = redis::cmd("hget")
+                    .arg(key)
+                    .arg(field)
+                    .query_async(&mut con)
+                    .await;
+
+                match result {
+                    Ok(value) => value,
+                    Err(err) => {
+                        info!("hget error = {:?}", err);
+                        None
+                    }
+                }
+            }
+            None => {
+                info!("hget error, redis not ready...");
+                None
+            }
+        }
+    }
+
+    pub async fn hgetall(key: &str) -> HashMap<String, String> {
+        let result = Redis::get_instance();
+
+        match result {
+            Some(res) => {
+                let mut con = res.con.clone();
+
+                let result: RedisResult<HashMap<String, String>> =
+                    redis::cmd("hgetall").arg(key).query_async(&mut con).await;
+
+                match result {
+                    Ok(value) => value,
+                    Err(err) => {
+                        info!("hgetall error = {:?}", err);
+                        HashMap::new()
+                    }
+                }
+            }
+            None => {
+                info!("hgetall error, redis not ready...");
+                HashMap::new()
+            }
+        }
+    }
+
+    pub async fn hdel(key: &str, field: &str) -> bool {
+        let result = Redis::get_instance();
+
+        match result {
+            Some(res) => {
+                let mut con = res.con.clone();
+
+                let result: RedisResult<()> = redis::cmd("hdel")
+                    .arg(key)
+                    .arg(field)
+                    .query_async(&mut con)
+                    .await;
+
+                if result.is_err() {
+                    info!("hdel error = {:?}", result.err());
+                    return false;
+                }
+                return true;
+            }
+            None => {
+                info!("hdel error, redis not ready...");
+            }
+        }
+        false
+    }
+
     pub async fn lock(key: &str) -> bool {
         Redis::lock_with_time(key, EXPIRE_TIME).await
     }
@@ -129,6 +339,35 @@ impl Redis {
     }
 }
 
+/// 订阅 channel, 等待下一条消息
+async fn next_message(client: &redis::Client, channel: &str) -> Option<String> {
+    let pubsub = client.get_async_connection().await;
+
+    if let Ok(con) = pubsub {
+        let mut pubsub_conn = con.into_pubsub();
+        let _ = pubsub_conn.subscribe(channel).await;
+        let mut pubsub_stream = pubsub_conn.into_on_message();
+
+        let data: Option<Msg> = pubsub_stream.next().await;
+
+        if let Some(msg) = data {
+            if msg.get_channel_name() == channel {
+                let result: RedisResult<String> = msg.get_payload();
+
+                if let Ok(id) = result {
+                    info!("found channel = {}, id = {}", msg.get_channel_name(), id);
+
+                    return Some(id);
+                }
+            }
+        }
+    } else {
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+    }
+
+    None
+}
+
 pub async fn init_redis(url: String, pub_sub: bool) {
     let client = redis::Client::open(url.clone()).unwrap();
     let result = client.get_tokio_connection_manager().await;
@@ -146,6 +385,22 @@ pub async fn init_redis(url: String, pub_sub: bool) {
                 return;
             }
 
+            // 开启取消订阅
+            let cancel_client = client.clone();
+            thread::spawn(move || {
+                let rt = tokio::runtime::Runtime::new().unwrap();
+
+                rt.block_on(async move {
+                    info!("start listern redis channel to listener cancel work ....");
+
+                    loop {
+                        if let Some(id) = next_message(&cancel_client, CANCEL_CHANNEL).await {
+                            crate::work::cancel_build_by_id(id).await;
+                        }
+                    }
+                });
+            });
+
             // 开启订阅
             thread::spawn(move || {
                 let rt = tokio::runtime::Runtime::new().unwrap();
@@ -154,32 +409,8 @@ pub async fn init_redis(url: String, pub_sub: bool) {
                     info!("start listern redis channel to listener build work ....");
 
                     loop {
-                        let pubsub = client.get_async_connection().await;
-
-                        if let Ok(con) = pubsub {
-                            let mut pubsub_conn = con.into_pubsub();
-                            let _ = pubsub_conn.subscribe(BUILD_CHANNEL).await;
-                            let mut pubsub_stream = pubsub_conn.into_on_message();
-
-                            let data: Option<Msg> = pubsub_stream.next().await;
-
-                            if let Some(msg) = data {
-                                if msg.get_channel_name() == BUILD_CHANNEL {
-                                    let result: RedisResult<String> = msg.get_payload();
-
-                                    if let Ok(id) = result {
-                                        info!(
-                                            "found channel = {}, id = {}",
-                                            msg.get_channel_name(),
-                                            id
-                                        );
-
-                                        crate::work::start_build_by_id(id).await;
-                                    }
-                                }
-                            }
-                        } else {
-                            tokio::time::sleep(Duration::from_millis(1000)).await;
+                        if let Some(id) = next_message(&client, BUILD_CHANNEL).await {
+                            crate::work::start_build_by_id(id).await;
                         }
                     }
                 });
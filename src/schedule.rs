@@ -0,0 +1,245 @@
+use std::{
+    process::Command,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use bson::{doc, DateTime, Document};
+use chrono::{Local, Utc};
+use log::info;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    build_params::{AppParams, BuildOverride, BuildParams},
+    db::{Db, COLLECTION_SCHEDULE},
+    error::AppError,
+    redis::{Redis, BUILD_CHANNEL},
+};
+
+/// 同一次触发的锁时间, 保证只有一个 manager 执行
+const FIRE_LOCK_TIME: i32 = 60 * 60;
+
+/// 定时打包
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Schedule {
+    pub name: String,
+    /// cron 表达式, 秒 分 时 日 月 周, 按服务器本地时间, 例如 `0 0 2 * * *`
+    pub cron: String,
+    pub params: BuildParams,
+    #[serde(default = "default_enable")]
+    pub enable: bool,
+    /// 分支没有新提交时不打包
+    #[serde(default)]
+    pub skip_unchanged: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_fire: Option<DateTime>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_revision: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_build_id: Option<Uuid>,
+    #[serde(default = "default_date")]
+    pub date: DateTime,
+}
+
+fn default_enable() -> bool {
+    true
+}
+
+fn default_date() -> DateTime {
+    DateTime(Utc::now())
+}
+
+/// after 之后的下一次触发时间
+pub fn next_fire(
+    cron: &str,
+    after: chrono::DateTime<Utc>,
+) -> Result<chrono::DateTime<Utc>, AppError> {
+    let schedule = cron::Schedule::from_str(cron)
+        .map_err(|e| AppError::Validation(format!("illegal cron {} : {}", cron, e)))?;
+
+    schedule
+        .after(&after.with_timezone(&Local))
+        .next()
+        .map(|t| t.with_timezone(&Utc))
+        .ok_or(AppError::Validation(format!("cron {} never fire", cron)))
+}
+
+/// 远程分支最新的提交, 在 blocking 线程中执行, 不阻塞 manager 的定时任务
+async fn remote_head(url: &str, branch: &str) -> Option<String> {
+    let url = url.to_string();
+    let branch = branch.to_string();
+
+    match tokio::task::spawn_blocking(move || ls_remote(&url, &branch)).await {
+        Ok(head) => head,
+        Err(err) => {
+            info!("ls-remote error = {}", err);
+            None
+        }
+    }
+}
+
+fn ls_remote(url: &str, branch: &str) -> Option<String> {
+    let output = Command::new("git")
+        .arg("ls-remote")
+        .arg(url)
+        .arg(format!("refs/heads/{}", branch))
+        .output();
+
+    match output {
+        Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
+            .split_whitespace()
+            .next()
+            .map(|s| s.to_string()),
+        Ok(o) => {
+            info!("ls-remote error = {}", String::from_utf8_lossy(&o.stderr));
+            None
+        }
+        Err(err) => {
+            info!("ls-remote error = {}", err);
+            None
+        }
+    }
+}
+
+impl Schedule {
+    pub async fn list() -> Result<Vec<Schedule>, AppError> {
+        let vec: Arc<Mutex<Vec<Schedule>>> = Arc::new(Mutex::new(Vec::new()));
+
+        Db::find(COLLECTION_SCHEDULE, doc! {}, None, &|schedule| {
+            vec.lock().unwrap().push(schedule)
+        })
+        .await?;
+
+        let list = vec.lock().unwrap().drain(..).collect::<Vec<Schedule>>();
+        Ok(list)
+    }
+
+    pub async fn save(&self) -> Result<(), AppError> {
+        let doc = bson::to_bson(self)?.as_document().unwrap().clone();
+
+        Db::save(COLLECTION_SCHEDULE, doc! {"name": self.name.clone()}, doc).await
+    }
+
+    /// 只更新触发记录, 避免覆盖同时修改的规则
+    async fn save_fire(&self) -> Result<(), AppError> {
+        let doc = bson::to_bson(self)?.as_document().unwrap().clone();
+        let set = ["last_fire", "last_revision", "last_build_id"]
+            .iter()
+            .filter_map(|k| doc.get(k).map(|v| (k.to_string(), v.clone())))
+            .collect::<Document>();
+
+        Db::update(COLLECTION_SCHEDULE, doc! {"name": self.name.clone()}, set).await
+    }
+
+    /// 下一次触发时间
+    pub fn next_fire(&self) -> Result<chrono::DateTime<Utc>, AppError> {
+        next_fire(&self.cron, self.last_fire.unwrap_or(self.date).0)
+    }
+
+    async fn fire(&mut self) -> Result<(), AppError> {
+        let mut build_p = self.params.clone();
+
+        let head = if self.skip_unchanged {
+            let branch = build_p
+                .version
+                .branch
+                .clone()
+                .unwrap_or("master".to_string());
+            remote_head(build_p.version.source_url.as_str(), &branch).await
+        } else {
+            None
+        };
+
+        if head.is_some() && head == self.last_revision {
+            info!("schedule {} skip, revision unchanged ...", self.name);
+            return Ok(());
+        }
+
+        // 固定到检查时的提交, 和 skip 判断保持一致
+        if head.is_some() {
+            BuildOverride {
+                revision: head.clone(),
+                ..Default::default()
+            }
+            .apply(&mut build_p);
+        }
+
+        let client = format!("schedule:{}", self.name);
+        let email = build_p.email.clone();
+        let mut app = AppParams::new(build_p, &client, email);
+        app.client = Some(client);
+
+        app.save_db().await?;
+        Redis::publish(BUILD_CHANNEL, &app.build_id.to_string()).await;
+
+        info!("schedule {} build {} ...", self.name, app.build_id);
+
+        self.last_revision = head;
+        self.last_build_id = Some(app.build_id);
+
+        Ok(())
+    }
+}
+
+/// manager 定时检查需要触发的定时打包
+pub async fn run_schedules() {
+    let list = match Schedule::list().await {
+        Ok(l) => l,
+        Err(err) => {
+            info!("schedule list error = {}", err);
+            return;
+        }
+    };
+
+    let now = Utc::now();
+    for mut schedule in list.into_iter().filter(|s| s.enable) {
+        let next = match schedule.next_fire() {
+            Ok(n) => n,
+            Err(err) => {
+                info!("schedule {} error = {}", schedule.name, err);
+                continue;
+            }
+        };
+
+        if next > now {
+            continue;
+        }
+
+        let key = format!("schedule:{}:{}", schedule.name, next.timestamp());
+        if !Redis::lock_with_time(&key, FIRE_LOCK_TIME).await {
+            continue;
+        }
+
+        // 停机期间错过的触发只补一次
+        schedule.last_fire = Some(DateTime(now));
+
+        if let Err(err) = schedule.fire().await {
+            info!("schedule {} fire error = {}", schedule.name, err);
+        }
+
+        if let Err(err) = schedule.save_fire().await {
+            info!("schedule {} save error = {}", schedule.name, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use super::next_fire;
+
+    #[test]
+    fn test_next_fire() {
+        let now = Utc.ymd(2021, 2, 1).and_hms(10, 0, 30);
+
+        let next = next_fire("0 * * * * *", now).unwrap();
+        assert_eq!(next, Utc.ymd(2021, 2, 1).and_hms(10, 1, 0));
+
+        let next = next_fire("0 0 2 * * *", now).unwrap();
+        assert!(next > now && next <= now + Duration::days(1));
+
+        assert!(next_fire("every night", now).is_err());
+    }
+}
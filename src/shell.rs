@@ -1,10 +1,22 @@
-use std::{fs::create_dir, fs::File, io::Write, process::Command};
+use std::{
+    collections::HashSet,
+    fs::create_dir,
+    fs::File,
+    io::Write,
+    os::unix::process::CommandExt,
+    process::{Command, Stdio},
+    sync::Mutex,
+};
 
 use crate::config::Config;
 use crate::utils;
-use log::{debug, error};
+use log::{debug, error, info};
+use once_cell::sync::Lazy;
 use uuid::Uuid;
 
+/// 正在执行的脚本进程组
+static RUNNING: Lazy<Mutex<HashSet<u32>>> = Lazy::new(|| Mutex::new(HashSet::new()));
+
 #[derive(Debug)]
 pub struct Shell {
     pub current_dir: String,
@@ -20,6 +32,11 @@ impl Shell {
     }
 
     pub fn run(&self, command: &str) -> Result<String, String> {
+        self.run_with_env(command, &[])
+    }
+
+    /// 密码等不写入脚本, 通过环境变量传入
+    pub fn run_with_env(&self, command: &str, envs: &[(&str, &str)]) -> Result<String, String> {
         if !utils::file_exist(&self.path) {
             let result = create_dir(&self.path);
             if result.is_err() {
@@ -39,17 +56,29 @@ impl Shell {
             debug!("command:{}", command);
         }
 
+        // 单独的进程组, 方便取消时杀掉整个进程树(gradle等)
         let result = Command::new("sh")
             .arg(&path)
             .current_dir(&self.current_dir)
             .env("ANDROID_HOME", Config::android_home())
-            .output();
+            .envs(envs.iter().cloned())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .spawn()
+            .and_then(|child| {
+                let pid = child.id();
+                RUNNING.lock().unwrap().insert(pid);
+                let output = child.wait_with_output();
+                RUNNING.lock().unwrap().remove(&pid);
+                output
+            });
 
         utils::remove_file(&path);
 
         match result {
             Ok(output) => {
-                if output.status.code().unwrap() != 0 {
+                if output.status.code() != Some(0) {
                     let err = String::from_utf8_lossy(&output.stderr).to_string();
                     // if !err.is_empty() {
                     //     warn!("stderr: {}", err);
@@ -64,6 +93,22 @@ impl Shell {
     }
 }
 
+/// 杀掉所有正在执行的脚本进程树
+pub fn kill_all() {
+    let pids: Vec<u32> = RUNNING.lock().unwrap().iter().cloned().collect();
+
+    for pid in pids {
+        info!("kill process group {} ...", pid);
+        let result = Command::new("kill")
+            .args(["-9", "--", &format!("-{}", pid)])
+            .output();
+
+        if let Err(err) = result {
+            error!("kill process group {} error = {}", pid, err);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     #[test]
@@ -0,0 +1,212 @@
+use bson::{doc, Bson, Document};
+use serde::Serialize;
+
+use crate::{
+    build_params::{CODE_BUILDING, CODE_CANCELLED, CODE_FAILED, CODE_WAITING},
+    db::{Db, COLLECTION_BUILD},
+    error::AppError,
+};
+
+/// 失败信息后面追加的日志地址, 统计时去掉
+const LOG_URL_SEP: &str = "\n 详细日志地址";
+const FAILURE_SIZE: i64 = 10;
+
+#[derive(Debug, Serialize, Default, PartialEq)]
+pub struct Percentile {
+    pub median: i64,
+    pub p95: i64,
+}
+
+#[derive(Debug, Serialize, Default)]
+pub struct StatItem {
+    pub name: String,
+    pub total: i64,
+    pub success: i64,
+    pub failed: i64,
+    pub cancelled: i64,
+    pub waiting: i64,
+    pub building: i64,
+    /// 成功数 / (成功数 + 失败数)
+    pub success_rate: f64,
+    /// 成功任务编译耗时, 单位秒
+    pub build_time: Percentile,
+    /// 排队耗时, 单位秒
+    pub wait_time: Percentile,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Failure {
+    pub msg: String,
+    pub count: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Stats {
+    pub projects: Vec<StatItem>,
+    pub workers: Vec<StatItem>,
+    pub failures: Vec<Failure>,
+}
+
+/// 计算中位数和 p95
+pub fn percentile(mut values: Vec<i64>) -> Percentile {
+    if values.is_empty() {
+        return Percentile::default();
+    }
+
+    values.sort();
+
+    let index = |p: f64| {
+        let i = (p * values.len() as f64).ceil() as usize;
+        values[i.max(1).min(values.len()) - 1]
+    };
+
+    Percentile {
+        median: index(0.5),
+        p95: index(0.95),
+    }
+}
+
+fn to_i64(value: Option<&Bson>) -> i64 {
+    match value {
+        Some(Bson::Int32(i)) => *i as i64,
+        Some(Bson::Int64(i)) => *i,
+        Some(Bson::Double(f)) => *f as i64,
+        _ => 0,
+    }
+}
+
+fn to_vec(value: Option<&Bson>) -> Vec<i64> {
+    match value {
+        Some(Bson::Array(array)) => array.iter().map(|v| to_i64(Some(v))).collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn count_code(code: i32) -> Document {
+    doc! {"$sum": {"$cond": [{"$eq": ["$code", code]}, 1, 0]}}
+}
+
+async fn group_by(filter: &Document, key: &str) -> Result<Vec<StatItem>, AppError> {
+    let pipeline = vec![
+        doc! {"$match": filter.clone()},
+        doc! {"$group": {
+            "_id": format!("${}", key),
+            "total": {"$sum": 1},
+            "success": count_code(0),
+            "failed": count_code(CODE_FAILED),
+            "cancelled": count_code(CODE_CANCELLED),
+            "waiting": count_code(CODE_WAITING),
+            "building": count_code(CODE_BUILDING),
+            "build_times": {"$push": {"$cond": [{"$eq": ["$code", 0]}, "$build_time", "$$REMOVE"]}},
+            "wait_times": {"$push": {"$cond": [
+                {"$gt": ["$start_time", Bson::Null]},
+                {"$subtract": ["$start_time", "$date"]},
+                "$$REMOVE"
+            ]}},
+        }},
+        doc! {"$sort": {"total": -1}},
+    ];
+
+    let docs = Db::aggregate(COLLECTION_BUILD, pipeline).await?;
+
+    let list = docs
+        .iter()
+        .map(|d| {
+            let success = to_i64(d.get("success"));
+            let failed = to_i64(d.get("failed"));
+            let finished = success + failed;
+
+            StatItem {
+                name: d.get_str("_id").unwrap_or("unknown").to_string(),
+                total: to_i64(d.get("total")),
+                success,
+                failed,
+                cancelled: to_i64(d.get("cancelled")),
+                waiting: to_i64(d.get("waiting")),
+                building: to_i64(d.get("building")),
+                success_rate: if finished > 0 {
+                    success as f64 / finished as f64
+                } else {
+                    0.0
+                },
+                build_time: percentile(to_vec(d.get("build_times"))),
+                wait_time: percentile(
+                    to_vec(d.get("wait_times"))
+                        .into_iter()
+                        .map(|t| t / 1000)
+                        .collect(),
+                ),
+            }
+        })
+        .collect();
+
+    Ok(list)
+}
+
+async fn failures(filter: &Document) -> Result<Vec<Failure>, AppError> {
+    let mut filter = filter.clone();
+    filter.insert("code", CODE_FAILED);
+
+    let pipeline = vec![
+        doc! {"$match": filter},
+        doc! {"$group": {
+            "_id": {"$arrayElemAt": [{"$split": ["$msg", LOG_URL_SEP]}, 0]},
+            "count": {"$sum": 1},
+        }},
+        doc! {"$sort": {"count": -1}},
+        doc! {"$limit": FAILURE_SIZE},
+    ];
+
+    let docs = Db::aggregate(COLLECTION_BUILD, pipeline).await?;
+
+    Ok(docs
+        .iter()
+        .map(|d| Failure {
+            msg: d.get_str("_id").unwrap_or("").to_string(),
+            count: to_i64(d.get("count")),
+        })
+        .collect())
+}
+
+/// 统计 filter 范围内的打包记录
+pub async fn stats(filter: Document) -> Result<Stats, AppError> {
+    Ok(Stats {
+        projects: group_by(&filter, "params.version.project_name").await?,
+        workers: group_by(&filter, "operate").await?,
+        failures: failures(&filter).await?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{percentile, Percentile};
+
+    #[test]
+    fn test_percentile() {
+        assert_eq!(percentile(vec![]), Percentile { median: 0, p95: 0 });
+        assert_eq!(
+            percentile(vec![30]),
+            Percentile {
+                median: 30,
+                p95: 30
+            }
+        );
+
+        let values = (1..=100).rev().collect::<Vec<i64>>();
+        assert_eq!(
+            percentile(values),
+            Percentile {
+                median: 50,
+                p95: 95
+            }
+        );
+
+        assert_eq!(
+            percentile(vec![120, 60, 300, 90]),
+            Percentile {
+                median: 90,
+                p95: 300
+            }
+        );
+    }
+}
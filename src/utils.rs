@@ -3,7 +3,7 @@ use log::{debug, error, info};
 use quick_xml::{
     events::BytesStart,
     events::{attributes::Attributes, BytesEnd, Event},
-    Error, Reader, Writer,
+    Reader, Writer,
 };
 use std::{
     collections::HashMap,
@@ -15,7 +15,7 @@ use std::{
 
 use std::fs;
 
-use crate::shell::Shell;
+use crate::{error::AppError, shell::Shell};
 
 #[macro_export]
 macro_rules! result_err {
@@ -25,6 +25,12 @@ macro_rules! result_err {
             format!("{:?}", err)
         }
     };
+    ($e:path) => {
+        |err| {
+            info!("err = {}", err);
+            $e(format!("{:?}", err))
+        }
+    };
 }
 
 #[macro_export]
@@ -43,7 +49,7 @@ pub fn clone_src(
     path: &str,
     branch: Option<String>,
     revision: Option<String>,
-) -> Result<(), String> {
+) -> Result<(), AppError> {
     info!("start git clone {} to {}", url, path);
 
     if file_exist(path) {
@@ -51,23 +57,24 @@ pub fn clone_src(
     }
 
     let shell = Shell::new("/tmp");
-    let mut command = format!("git clone {} ", url);
+    let mut command = "git clone".to_string();
 
+    // 参数可能来自 webhook, 都需要转义
     if let Some(b) = branch {
         if !b.is_empty() {
-            command.push_str(&format!(" -b {} ", &b));
+            command.push_str(&format!(" -b {}", shell_quote(&b)));
         }
     }
 
-    command.push_str(path.clone());
+    command.push_str(&format!(" -- {} {}", shell_quote(url), shell_quote(path)));
 
-    shell.run(&command)?;
+    shell.run(&command).map_err(AppError::Scm)?;
 
     if let Some(commit) = revision {
         let shell = Shell::new(path);
         info!(" checkout {} ", &commit);
-        let command = format!("git checkout {}", commit);
-        shell.run(&command)?;
+        let command = format!("git checkout {}", shell_quote(&commit));
+        shell.run(&command).map_err(AppError::Scm)?;
     }
 
     Ok(())
@@ -95,6 +102,16 @@ pub fn file_exist(path: &str) -> bool {
     fs::metadata(path).is_ok()
 }
 
+/// 用单引号包起来作为 shell 参数
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// 逐字节比较, 避免按耗时猜测
+pub fn const_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |r, (x, y)| r | (x ^ y)) == 0
+}
+
 fn has_android_name(attrs: &Attributes, meta: &HashMap<String, String>) -> bool {
     attrs.clone().into_iter().any(|s| {
         if let Ok(r) = s {
@@ -118,7 +135,7 @@ pub fn change_xml<'a>(
     version_name: Option<String>,
     app_name: Option<String>,
     path: Option<&'a str>,
-) -> Result<(), Error> {
+) -> Result<(), AppError> {
     let mut reader = Reader::from_str(xml);
 
     reader.trim_text(true);
@@ -196,7 +213,7 @@ pub fn change_xml<'a>(
                 assert!(writer.write_event(&e).is_ok())
             }
             Err(e) => {
-                return Err(e);
+                return Err(e.into());
             }
         }
         buf.clear();
@@ -213,7 +230,7 @@ pub fn change_xml<'a>(
             file.write_all(&result).expect("write failed");
         } else {
             error!("create file error, {}", p);
-            return Err(Error::UnexpectedEof("create file error".to_string()));
+            return Err(AppError::Internal(format!("create file error, {}", p)));
         }
     }
 
@@ -221,7 +238,7 @@ pub fn change_xml<'a>(
 }
 
 #[allow(unused_must_use)]
-pub fn change_properies_file(path: &str, config: &HashMap<String, String>) -> Result<(), String> {
+pub fn change_properies_file(path: &str, config: &HashMap<String, String>) -> Result<(), AppError> {
     if !file_exist(path) {
         // 先创建parent dir
         let p = Path::new(path);
@@ -232,20 +249,20 @@ pub fn change_properies_file(path: &str, config: &HashMap<String, String>) -> Re
 
     match File::open(path) {
         Ok(f) => {
-            let mut map2 = read(BufReader::new(f)).map_err(result_err!())?;
+            let mut map2 = read(BufReader::new(f)).map_err(result_err!(AppError::Internal))?;
             // let shell = Shell::new("/tmp");
 
             for (key, value) in config {
                 map2.insert(key.clone(), value.clone());
             }
 
-            let f = File::create(path).map_err(result_err!())?;
+            let f = File::create(path)?;
 
-            write(BufWriter::new(f), &map2).map_err(result_err!())?;
+            write(BufWriter::new(f), &map2).map_err(result_err!(AppError::Internal))?;
 
             Ok(())
         }
-        Err(error) => Err(error.to_string()),
+        Err(error) => Err(error.into()),
     }
 }
 
@@ -338,4 +355,10 @@ mod tests {
         );
         assert!(None == result.err());
     }
+
+    #[test]
+    fn shell_quote_test() {
+        assert_eq!(super::shell_quote("/tmp/a b"), "'/tmp/a b'");
+        assert_eq!(super::shell_quote("it's"), "'it'\\''s'");
+    }
 }
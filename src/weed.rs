@@ -4,7 +4,7 @@ use serde::{Deserialize, Serialize};
 use tokio::fs::File;
 use tokio_util::codec::{BytesCodec, FramedRead};
 
-use crate::result_err;
+use crate::{error::AppError, result_err};
 
 const ASSIGN_URL: &'static str = "http://gitlab.justsafe.com:9333/dir/assign";
 const LOOKUP_URL: &'static str = "http://gitlab.justsafe.com:9333/dir/lookup?fileId=";
@@ -23,7 +23,7 @@ macro_rules! get_upload_url {
     };
 }
 
-pub async fn upload(path: &str, file_name: String) -> Result<String, String> {
+pub async fn upload(path: &str, file_name: String) -> Result<String, AppError> {
     info!("upload file {} ...", path);
 
     if crate::config::Config::enable_no_upload() {
@@ -31,14 +31,17 @@ pub async fn upload(path: &str, file_name: String) -> Result<String, String> {
     }
 
     if !crate::utils::file_exist(path) {
-        return Err(format!("{} not exist!!", path));
+        return Err(AppError::Upload(format!("{} not exist!!", path)));
     }
 
     match reqwest::get(ASSIGN_URL).await {
         Ok(res) => {
             let header = res.headers().clone();
 
-            let json = res.json::<Assign>().await.map_err(result_err!())?;
+            let json = res
+                .json::<Assign>()
+                .await
+                .map_err(result_err!(AppError::Upload))?;
 
             info!("res = {:?}", json);
 
@@ -49,7 +52,9 @@ pub async fn upload(path: &str, file_name: String) -> Result<String, String> {
 
             let mut form = Form::new();
             form = {
-                let file = File::open(path).await.map_err(result_err!())?;
+                let file = File::open(path)
+                    .await
+                    .map_err(result_err!(AppError::Upload))?;
 
                 let reader = Body::wrap_stream(FramedRead::new(file, BytesCodec::new()));
                 form.part("file", Part::stream(reader).file_name(file_name))
@@ -61,7 +66,7 @@ pub async fn upload(path: &str, file_name: String) -> Result<String, String> {
                 .multipart(form)
                 .send()
                 .await
-                .map_err(result_err!())?;
+                .map_err(result_err!(AppError::Upload))?;
 
             let code = { result.status() };
             let s = { result.text().await.unwrap() };
@@ -70,17 +75,17 @@ pub async fn upload(path: &str, file_name: String) -> Result<String, String> {
             if code == StatusCode::CREATED {
                 Ok(fid)
             } else {
-                Err(s)
+                Err(AppError::Upload(s))
             }
         }
-        Err(err) => return Err(err.to_string()),
+        Err(err) => Err(AppError::Upload(err.to_string())),
     }
 }
 
-pub async fn delete(fid: &str) -> Result<(), String> {
+pub async fn delete(fid: &str) -> Result<(), AppError> {
     let res = reqwest::get(format!("{}{}", LOOKUP_URL, fid).as_str())
         .await
-        .map_err(result_err!())?;
+        .map_err(result_err!(AppError::Upload))?;
 
     let auth = { res.headers().get(AUTH_KEY) };
 
@@ -91,7 +96,7 @@ pub async fn delete(fid: &str) -> Result<(), String> {
         .header(AUTH_KEY, auth.unwrap())
         .send()
         .await
-        .map_err(result_err!())?;
+        .map_err(result_err!(AppError::Upload))?;
 
     let code = { result.status() };
     let s = { result.text().await.unwrap() };
@@ -100,7 +105,7 @@ pub async fn delete(fid: &str) -> Result<(), String> {
     if code == StatusCode::ACCEPTED {
         Ok(())
     } else {
-        Err(s)
+        Err(AppError::Upload(s))
     }
 }
 
@@ -1,4 +1,11 @@
-use std::{collections::HashMap, fs, path::Path, process::Command};
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::Write,
+    os::unix::fs::OpenOptionsExt,
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
 
 use crate::redis::Redis;
 use bson::Bson;
@@ -6,9 +13,17 @@ use log::{error, info, warn};
 use shell::Shell;
 use uuid::Uuid;
 
-use crate::{build_params, config, get_upload_url, utils::file_exist};
 use crate::{
-    build_params::{AppParams, Scm},
+    artifact::{self, Artifact, ArtifactKind},
+    axml::ApkMeta,
+    build_params, config,
+    error::AppError,
+    get_upload_url, icon,
+    keystore::{self, Keystore},
+    utils::file_exist,
+};
+use crate::{
+    build_params::{AppParams, Scm, Variant},
     framework::base::BuildStep,
 };
 use crate::{config::Config, framework::*};
@@ -18,11 +33,74 @@ use crate::{
 };
 use crate::{get_default, utils};
 
+const ZKM_JAR: &str = "/lib/ZKM.jar";
+const ZKM_CHANGE_LOG: &str = "ChangeLog.txt";
+
+/// 没有指定 module_name 时的模块目录
+const DEFAULT_MODULE: &str = "app";
+
 pub fn get_source_path(build_id: Uuid) -> String {
     let path = config::Config::cache_home();
     path + "/apps/" + &build_id.to_string()
 }
 
+/// 指定的 gradle 模块, 多级模块用 `:` 分隔, 例如 `apps:seed`
+fn get_module_name(app: &AppParams) -> Result<Option<String>, AppError> {
+    let module = match &app.params.version.module_name {
+        Some(m) if !m.trim_matches(':').is_empty() => m.trim_matches(':'),
+        _ => return Ok(None),
+    };
+
+    let legal = module.split(':').all(|s| {
+        !s.is_empty()
+            && s.chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    });
+    if !legal {
+        return Err(AppError::Validation(format!(
+            "illegal module_name {}",
+            module
+        )));
+    }
+
+    // 模块不存在时直接失败, 不使用默认模块, 避免打出错误的 apk
+    let path = format!(
+        "{}/{}",
+        get_source_path(app.build_id),
+        module.replace(':', "/")
+    );
+    if !Path::new(&path).is_dir() {
+        return Err(AppError::Validation(format!(
+            "module {} not found in source",
+            module
+        )));
+    }
+
+    Ok(Some(module.to_string()))
+}
+
+/// 模块源码目录, 默认为 app
+pub fn get_module_path(app: &AppParams) -> Result<String, AppError> {
+    let module = get_module_name(app)?
+        .map(|m| m.replace(':', "/"))
+        .unwrap_or_else(|| DEFAULT_MODULE.to_string());
+    let path = format!("{}/{}", get_source_path(app.build_id), module);
+
+    if !Path::new(&path).is_dir() {
+        return Err(AppError::Validation(format!(
+            "module {} not found in source",
+            module
+        )));
+    }
+
+    Ok(path)
+}
+
+/// 模块的 gradle 编译产物目录
+fn get_outputs_path(app: &AppParams) -> Result<PathBuf, AppError> {
+    Ok(Path::new(&get_module_path(app)?).join("build/outputs"))
+}
+
 pub fn get_log_file(build_id: Uuid) -> String {
     let path = config::Config::cache_home();
     if !utils::file_exist(&(path.clone() + "/logs")) {
@@ -33,7 +111,7 @@ pub fn get_log_file(build_id: Uuid) -> String {
     path + "/logs/" + &build_id.to_string() + ".txt"
 }
 
-pub fn fetch_source(app: &AppParams) -> Result<(), String> {
+pub fn fetch_source(app: &AppParams) -> Result<(), AppError> {
     let url = app.params.version.source_url.clone();
 
     if Scm::Git == app.params.version.scm.clone().unwrap_or(Scm::Git) {
@@ -44,67 +122,339 @@ pub fn fetch_source(app: &AppParams) -> Result<(), String> {
             app.params.version.revision.clone(),
         )
     } else {
-        Err("不支持的scm".to_string())
+        Err(AppError::Validation("不支持的scm".to_string()))
     }
 }
 
-fn get_channel_command<'a>(channel: Option<String>, log: &'a str) -> String {
-    let command = match channel {
-        Some(s) => format!(
-            "./gradlew assemble{}{}Release --no-daemon > {}",
-            (&s[..1].to_string()).to_uppercase(),
-            &s[1..],
-            &log
-        ),
-
-        None => format!("./gradlew assembleRelease --no-daemon >  {}", &log),
-    };
+fn get_channel_command(module: Option<&str>, variant: &Variant, bundle: bool, log: &str) -> String {
+    let mut tasks = vec![variant.task()];
+    if bundle {
+        tasks.push(variant.bundle_task());
+    }
 
-    return command;
+    // 没有指定模块时使用根项目的任务
+    let tasks = tasks
+        .iter()
+        .map(|t| match module {
+            Some(m) => format!(":{}:{}", m, t),
+            None => t.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    format!("./gradlew {} --no-daemon >> {}", tasks, &log)
 }
 
-pub fn release_build(app: &AppParams) -> Result<(), String> {
+pub fn release_build(app: &AppParams) -> Result<(), AppError> {
     let dir = get_source_path(app.build_id);
     let log = get_log_file(app.build_id);
+    let module = get_module_name(app)?;
+    let variant = app.params.version.variant()?;
 
     info!("start build in .... {}  log = {}", &dir, &log);
 
     let shell = shell::Shell::new(&dir);
 
-    shell.run(&format!("chmod a+x gradlew && ./gradlew clean > {}", &log))?;
+    // clean 时新建日志, 之后的步骤都追加写入, 避免覆盖导致实时日志偏移错乱
+    shell
+        .run(&format!(
+            "chmod a+x gradlew && ./gradlew clean --no-daemon > {}",
+            &log
+        ))
+        .map_err(AppError::Gradle)?;
+
+    shell
+        .run(&get_channel_command(
+            module.as_deref(),
+            &variant,
+            app.params.version.bundle.unwrap_or(false),
+            &log,
+        ))
+        .map_err(AppError::Gradle)?;
+
+    Ok(())
+}
+
+/// 相对源码目录的路径, 不能跳出源码目录
+fn source_file(dir: &str, path: &str) -> Result<PathBuf, AppError> {
+    let relative = Path::new(path);
+    if relative.is_absolute() || relative.components().any(|c| c == Component::ParentDir) {
+        return Err(AppError::Validation(format!("illegal path {}", path)));
+    }
+
+    Ok(Path::new(dir).join(relative))
+}
 
-    shell.run(&get_channel_command(
-        app.params.version.channel.clone(),
-        &log,
-    ))?;
+/// 在打包日志中写入分段标题
+fn log_section(log: &str, title: &str) -> Result<(), AppError> {
+    let mut file = OpenOptions::new().create(true).append(true).open(log)?;
+    writeln!(
+        file,
+        "\n------------------------ {} ------------------------\n",
+        title
+    )?;
 
     Ok(())
 }
 
-pub async fn upload_build(app: &mut AppParams) -> Result<(), String> {
+/// 使用 /lib/ZKM.jar 混淆, ChangeLog 放到 outputs/zkm 下随 apk 上传
+pub fn obfuscate_build(app: &AppParams) -> Result<(), AppError> {
+    let obfuscate = match &app.params.obfuscate {
+        Some(o) => o,
+        None => return Ok(()),
+    };
+    obfuscate.validate()?;
+
     let dir = get_source_path(app.build_id);
-    let shell = shell::Shell::new(&dir);
-    let apk = shell.run("find `pwd` -name '*release.apk'")?;
-    info!("found apk ... {}", apk);
+    let log = get_log_file(app.build_id);
+    let script = source_file(&dir, &obfuscate.script)?;
+    let change_log = source_file(
+        &dir,
+        obfuscate.change_log.as_deref().unwrap_or(ZKM_CHANGE_LOG),
+    )?;
+
+    if !script.is_file() {
+        return Err(AppError::Validation(format!(
+            "zkm script {} not exist",
+            obfuscate.script
+        )));
+    }
+
+    info!("start zkm {} ...", script.display());
+    log_section(&log, "zkm")?;
+
+    let shell = Shell::new(&dir);
+    shell
+        .run(&format!(
+            "java -jar {} {} >> {}",
+            ZKM_JAR,
+            utils::shell_quote(&script.to_string_lossy()),
+            &log
+        ))
+        .map_err(|e| AppError::Gradle(format!("zkm error : {}", e)))?;
+
+    if let Some(ref task) = obfuscate.gradle_task {
+        log_section(&log, "gradle")?;
+
+        shell
+            .run(&format!("./gradlew {} --no-daemon >> {}", task, &log))
+            .map_err(AppError::Gradle)?;
+    }
+
+    if change_log.is_file() {
+        let outputs = get_outputs_path(app)?.join("zkm");
+        fs::create_dir_all(&outputs)?;
+        fs::copy(&change_log, outputs.join(ZKM_CHANGE_LOG))?;
+    } else {
+        warn!("zkm change log {} not found", change_log.display());
+    }
+
+    Ok(())
+}
+
+/// 使用 manager 保存的签名证书签名, 并记录证书 sha256
+pub async fn sign_build(app: &mut AppParams) -> Result<(), AppError> {
+    let signing = match &app.params.signing {
+        Some(s) => s.clone(),
+        None => return Ok(()),
+    };
 
-    let fid = crate::weed::upload(
-        apk.trim(),
-        format!(
-            "{}_{}.apk",
-            get_default!(app.params.version.project_name),
-            get_default!(app.params.version.version_name)
-        ),
-    )
-    .await?;
+    let keystore = Keystore::find(&signing.keystore).await?;
+    let (store_password, key_password) = keystore.passwords()?;
+
+    let dir = get_source_path(app.build_id);
+    let outputs = get_outputs_path(app)?;
+    let variant = app.params.version.variant()?;
+
+    // 未签名的 apk 签名后去掉 -unsigned 后缀
+    for path in artifact::unsigned_apks(&outputs, &variant)? {
+        let name = path.to_string_lossy().replace("-unsigned.apk", ".apk");
+        fs::rename(&path, &name)?;
+    }
 
-    app.fid = Some(fid);
+    // keystore 只允许当前用户读写
+    let ks_file = format!("{}/.signing.keystore", dir);
+    utils::remove_file(&ks_file);
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&ks_file)?
+        .write_all(&keystore.bytes()?)?;
+
+    let result = sign_apks(
+        &dir,
+        &outputs,
+        &variant,
+        &ks_file,
+        &keystore.key_alias,
+        &[
+            ("KS_PASS", store_password.as_str()),
+            ("KEY_PASS", key_password.as_str()),
+        ],
+    );
+    utils::remove_file(&ks_file);
+
+    app.cert_sha256 = Some(result?);
 
     Ok(())
 }
 
-pub fn change_config(app: &AppParams) -> Result<(), String> {
+fn sign_apks(
+    dir: &str,
+    outputs: &Path,
+    variant: &Variant,
+    ks_file: &str,
+    alias: &str,
+    envs: &[(&str, &str)],
+) -> Result<String, AppError> {
+    let shell = Shell::new(dir);
+    let apksigner = "$(ls -d $ANDROID_HOME/build-tools/*/ | sort -V | tail -n 1)apksigner";
+
+    let mut cert: Option<String> = None;
+    for (kind, path) in artifact::discover(outputs, variant)? {
+        if kind != ArtifactKind::Apk {
+            continue;
+        }
+        let apk = path.to_string_lossy();
+        info!("sign {} ...", apk);
+
+        shell
+            .run_with_env(
+                &format!(
+                    "{} sign --ks {} --ks-pass env:KS_PASS --ks-key-alias {} --key-pass env:KEY_PASS \
+                    --v1-signing-enabled true --v2-signing-enabled true --v3-signing-enabled true {}",
+                    apksigner,
+                    utils::shell_quote(ks_file),
+                    utils::shell_quote(alias),
+                    utils::shell_quote(&apk)
+                ),
+                envs,
+            )
+            .map_err(|e| AppError::Internal(format!("apksigner sign error : {}", e)))?;
+
+        let output = shell
+            .run(&format!(
+                "{} verify --print-certs -v {}",
+                apksigner,
+                utils::shell_quote(&apk)
+            ))
+            .map_err(|e| AppError::Verify(format!("apksigner verify error : {}", e)))?;
+        let (sha256, schemes) = keystore::parse_verify(&output)?;
+        info!("signed {} cert = {}, schemes = {:?}", apk, sha256, schemes);
+
+        match cert {
+            Some(ref c) if *c != sha256 => {
+                return Err(AppError::Verify(format!(
+                    "{} cert {} not match {}",
+                    apk, sha256, c
+                )))
+            }
+            _ => cert = Some(sha256),
+        }
+    }
+
+    cert.ok_or_else(|| AppError::Gradle(format!("apk not found in {}", outputs.display())))
+}
+
+/// 检查 apk 的 manifest 是否和打包参数一致
+pub fn verify_build(app: &mut AppParams) -> Result<(), AppError> {
+    let outputs = get_outputs_path(app)?;
+    let variant = app.params.version.variant()?;
+
+    let apks = artifact::discover(&outputs, &variant)?
+        .into_iter()
+        .filter(|(kind, _)| *kind == ArtifactKind::Apk)
+        .map(|(_, path)| path)
+        .collect::<Vec<PathBuf>>();
+
+    let mut errors = Vec::new();
+    for path in apks.iter() {
+        let meta = ApkMeta::from_apk(path)?;
+        info!("verify {} ... {:?}", path.display(), meta);
+
+        errors.extend(
+            meta.verify(&app.params)
+                .into_iter()
+                .map(|e| format!("{} : {}", path.file_name().unwrap().to_string_lossy(), e)),
+        );
+
+        if app.apk.is_none() || path.to_string_lossy().contains("universal") {
+            app.apk = Some(meta);
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(AppError::Verify(errors.join("\n")))
+    }
+}
+
+pub async fn upload_build(app: &mut AppParams) -> Result<(), AppError> {
+    let dir = get_source_path(app.build_id);
+    let outputs = get_outputs_path(app)?;
+    let variant = app.params.version.variant()?;
+
+    let files = artifact::discover(&outputs, &variant)?;
+    let apk_count = files
+        .iter()
+        .filter(|(kind, _)| *kind == ArtifactKind::Apk)
+        .count();
+    if apk_count == 0 {
+        return Err(AppError::Gradle(format!(
+            "apk not found in {}",
+            outputs.join(variant.apk_dir()).display()
+        )));
+    }
+
+    let prefix = format!(
+        "{}_{}",
+        get_default!(app.params.version.project_name),
+        get_default!(app.params.version.version_name)
+    );
+
+    let mut artifacts = Vec::new();
+    for (kind, path) in files {
+        info!("found {} ... {}", kind.as_str(), path.display());
+
+        let file_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let name = match kind {
+            ArtifactKind::Apk if apk_count == 1 => format!("{}.apk", prefix),
+            ArtifactKind::Mapping => format!("{}_mapping.txt", prefix),
+            ArtifactKind::Changelog => format!("{}_zkm_{}", prefix, file_name),
+            _ => format!("{}_{}", prefix, file_name),
+        };
+
+        let size = fs::metadata(&path)?.len();
+        let sha256 = artifact::sha256(&path)?;
+        let fid = crate::weed::upload(&path.to_string_lossy(), name.clone()).await?;
+
+        artifacts.push(Artifact {
+            kind,
+            path: path
+                .strip_prefix(&dir)
+                .unwrap_or(&path)
+                .to_string_lossy()
+                .to_string(),
+            name,
+            size,
+            sha256,
+            fid,
+        });
+    }
+
+    app.fid = artifact::primary_apk(&artifacts).map(|a| a.fid.clone());
+    app.artifacts = Some(artifacts);
+
+    Ok(())
+}
+
+pub fn change_config(app: &AppParams) -> Result<(), AppError> {
     let source = get_source_path(app.build_id);
-    let android_manifest_xml = source.clone() + "/app/src/main/AndroidManifest.xml";
+    let module = get_module_path(app)?;
+    let android_manifest_xml = module.clone() + "/src/main/AndroidManifest.xml";
     let shell = Shell::new(&source);
 
     if utils::file_exist(&android_manifest_xml) {
@@ -120,7 +470,7 @@ pub fn change_config(app: &AppParams) -> Result<(), String> {
             }
         }
 
-        let output = shell.run("git rev-parse HEAD")?;
+        let output = shell.run("git rev-parse HEAD").map_err(AppError::Scm)?;
         meta.insert("git_version".to_string(), output.trim().to_string());
 
         info!("change AndroidManifestXml...");
@@ -131,7 +481,7 @@ pub fn change_config(app: &AppParams) -> Result<(), String> {
         };
 
         match utils::change_xml(
-            &fs::read_to_string(Path::new(&android_manifest_xml.as_str())).unwrap(),
+            &fs::read_to_string(Path::new(&android_manifest_xml.as_str()))?,
             &meta,
             app.params.version.version_code.clone(),
             app.params.version.version_name.clone(),
@@ -141,36 +491,73 @@ pub fn change_config(app: &AppParams) -> Result<(), String> {
             Ok(_) => {}
             Err(e) => {
                 error!("{}", e.to_string());
-                return Err(e.to_string());
+                return Err(e);
             }
         }
     } else {
-        return Err("AndroidManifestXml not exist !!".to_string());
+        return Err(AppError::Validation(
+            "AndroidManifestXml not exist !!".to_string(),
+        ));
     }
 
     if let Some(app_config) = &app.params.configs.app_config {
         if !app_config.is_empty() {
             info!("change properies file...");
 
-            let file = &format!("{}/app/src/main/assets/config.properties", source);
+            let file = &format!("{}/src/main/assets/config.properties", module);
             utils::change_properies_file(file, app_config)?
         }
     }
 
-    let gradle_file = format!("{}/app/build.gradle", source);
+    let gradle_file = format!("{}/build.gradle", module);
 
     if let Some(_) = &app.params.version.version_code {
-        shell.run(&format!("sed -i -e '/versionCode .*/d' {} ", gradle_file))?;
+        shell
+            .run(&format!("sed -i -e '/versionCode .*/d' {} ", gradle_file))
+            .map_err(AppError::Internal)?;
     }
 
     if let Some(_) = &app.params.version.version_name {
-        shell.run(&format!("sed -i -e '/versionName .*/d' {} ", gradle_file))?;
+        shell
+            .run(&format!("sed -i -e '/versionName .*/d' {} ", gradle_file))
+            .map_err(AppError::Internal)?;
     }
 
     Ok(())
 }
 
-pub async fn start(app: &mut AppParams) -> Result<(), String> {
+/// 替换 base_config.app_icon 指定的图标
+pub async fn change_icon(app: &AppParams) -> Result<(), AppError> {
+    let src = match app
+        .params
+        .configs
+        .base_config
+        .as_ref()
+        .and_then(|c| c.app_icon.clone())
+    {
+        Some(s) if !s.trim().is_empty() => s,
+        _ => return Ok(()),
+    };
+
+    info!("change app icon...");
+
+    let module = get_module_path(app)?;
+    let manifest = fs::read_to_string(format!("{}/src/main/AndroidManifest.xml", module))?;
+    let img = icon::load(&src).await?;
+
+    // 各尺寸缩放比较耗时, 不占用异步线程
+    tokio::task::spawn_blocking(move || {
+        icon::replace(
+            Path::new(&format!("{}/src/main/res", module)),
+            &manifest,
+            &img,
+        )
+    })
+    .await
+    .map_err(|e| AppError::Internal(format!("replace app icon error : {}", e)))?
+}
+
+pub async fn start(app: &mut AppParams) -> Result<(), AppError> {
     match app.params.configs.framework {
         crate::build_params::Framework::Normal => {
             normal::NormalBuild().step(app).await?;
@@ -196,7 +583,12 @@ pub async fn start_build_by_id(id: String) {
                     let result = bson::from_bson::<AppParams>(Bson::Document(doc));
                     match result {
                         Ok(app) => {
-                            start_build(app).await;
+                            if app.status.is_finished() {
+                                info!("start_build_by_id {} already finished", app.build_id);
+                                Redis::unlock(&app.build_id.to_string()).await;
+                            } else {
+                                start_build(app).await;
+                            }
                         }
                         Err(err) => {
                             info!("{}", err);
@@ -215,11 +607,51 @@ pub async fn start_build_by_id(id: String) {
     }
 }
 
+/// 取消正在本机编译的任务
+pub async fn cancel_build_by_id(id: String) {
+    if Config::build_id() == Some(id.clone()) {
+        info!("cancel build {} ... ", id);
+        Config::change_cancelled(true);
+        shell::kill_all();
+    }
+}
+
+/// 任务被取消时中断打包流程
+pub fn check_cancelled() -> Result<(), AppError> {
+    if Config::is_cancelled() {
+        Err(AppError::Cancelled("任务已取消".to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// 上传打包日志
+async fn upload_log(app: &mut AppParams) -> Option<String> {
+    let log = get_log_file(app.build_id);
+
+    if file_exist(&log) {
+        match crate::weed::upload(&log, format!("{}.txt", app.build_id)).await {
+            Ok(fid) => {
+                app.log_fid = Some(fid.clone());
+                return Some(fid);
+            }
+            Err(err) => {
+                info!("error upload log file : {}", err);
+            }
+        }
+    }
+
+    None
+}
+
 async fn start_build(mut app: AppParams) {
     Config::change_building(true);
+    Config::change_cancelled(false);
+    Config::change_build_id(Some(app.build_id.to_string()));
 
     info!("start build {} ... ", app.build_id);
     let time = chrono::Utc::now().timestamp();
+    app.start_time = Some(bson::DateTime(chrono::Utc::now()));
     app.status = build_params::BuildStatus::building();
     app.operate = Some(Config::ip());
     if let Err(e) = app.save_db().await {
@@ -232,6 +664,19 @@ async fn start_build(mut app: AppParams) {
             app.build_time = (chrono::Utc::now().timestamp() - time) as i16;
 
             app.status = build_params::BuildStatus::success();
+            upload_log(&mut app).await;
+            if let Err(e) = app.save_db().await {
+                info!("{}", e);
+            }
+        }
+        // 取消时正在执行的命令被 kill, 返回的可能是其他错误
+        Err(e) if matches!(e, AppError::Cancelled(_)) || Config::is_cancelled() => {
+            info!("{}  build cancelled ....", app.build_id);
+
+            app.build_time = (chrono::Utc::now().timestamp() - time) as i16;
+
+            app.status = build_params::BuildStatus::cancelled();
+            upload_log(&mut app).await;
             if let Err(e) = app.save_db().await {
                 info!("{}", e);
             }
@@ -244,23 +689,14 @@ async fn start_build(mut app: AppParams) {
 
             app.build_time = (chrono::Utc::now().timestamp() - time) as i16;
 
-            app.status = build_params::BuildStatus::failed(e);
+            app.status = build_params::BuildStatus::failed(e.to_string());
 
-            let log = get_log_file(app.build_id);
-
-            if file_exist(&log) {
-                match crate::weed::upload(&log, format!("{}.txt", app.build_id)).await {
-                    Ok(fid) => {
-                        app.status.msg = format!(
-                            "{}\n 详细日志地址: {}",
-                            app.status.msg,
-                            get_upload_url!(fid)
-                        );
-                    }
-                    Err(err) => {
-                        info!("error upload log file : {}", err);
-                    }
-                }
+            if let Some(fid) = upload_log(&mut app).await {
+                app.status.msg = format!(
+                    "{}\n 详细日志地址: {}",
+                    app.status.msg,
+                    get_upload_url!(fid)
+                );
             }
 
             if let Err(err) = app.save_db().await {
@@ -278,13 +714,15 @@ async fn start_build(mut app: AppParams) {
 
     Redis::unlock(&app.build_id.to_string()).await;
 
+    Config::change_build_id(None);
+    Config::change_cancelled(false);
     Config::change_building(false);
 }
 
 #[cfg(test)]
 mod tests {
     use crate::{
-        build_params::{AppParams, BuildParams},
+        build_params::{AppParams, BuildParams, Variant},
         utils,
     };
 
@@ -349,7 +787,39 @@ mod tests {
     #[test]
     fn test_channel_command() {
         let log = "111";
-        let command = super::get_channel_command(Some("master".to_string()), log);
-        assert_eq!(command, "./gradlew assembleMasterRelease --no-daemon > 111")
+        let variant = Variant {
+            flavors: vec!["master".to_string()],
+            build_type: "release".to_string(),
+        };
+        let command = super::get_channel_command(None, &variant, false, log);
+        assert_eq!(
+            command,
+            "./gradlew assembleMasterRelease --no-daemon >> 111"
+        );
+
+        let variant = Variant {
+            flavors: Vec::new(),
+            build_type: "release".to_string(),
+        };
+        let command = super::get_channel_command(Some("apps:seed"), &variant, false, log);
+        assert_eq!(
+            command,
+            "./gradlew :apps:seed:assembleRelease --no-daemon >> 111"
+        );
+
+        let command = super::get_channel_command(Some("apps:seed"), &variant, true, log);
+        assert_eq!(
+            command,
+            "./gradlew :apps:seed:assembleRelease :apps:seed:bundleRelease --no-daemon >> 111"
+        )
+    }
+
+    #[test]
+    fn test_source_file() {
+        let path = super::source_file("/tmp/apps/1", "zkm/script.txt").unwrap();
+        assert_eq!(path.to_str().unwrap(), "/tmp/apps/1/zkm/script.txt");
+
+        assert!(super::source_file("/tmp/apps/1", "../2/script.txt").is_err());
+        assert!(super::source_file("/tmp/apps/1", "/etc/passwd").is_err());
     }
 }
@@ -0,0 +1,185 @@
+use std::{
+    cmp::Reverse,
+    process::Command,
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::info;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::Config,
+    redis::{Redis, WORKERS},
+};
+
+/// 超过该时间没有心跳视为离线
+const STALE_SECS: i64 = 60;
+/// 超过该时间没有心跳直接从登记表中删除
+const REMOVE_SECS: i64 = 60 * 60 * 24 * 7;
+/// 缓存目录占用计算间隔
+const CACHE_USAGE_INTERVAL: Duration = Duration::from_secs(60 * 10);
+
+static CACHE_USAGE: Lazy<Mutex<(Option<Instant>, Option<u64>)>> =
+    Lazy::new(|| Mutex::new((None, None)));
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Worker {
+    pub name: String,
+    pub addr: String,
+    pub version: String,
+    pub android_home: String,
+    pub cache_home: String,
+    /// 缓存目录占用, 单位 KB
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_usage: Option<u64>,
+    pub building: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub build_id: Option<String>,
+    /// 最后心跳时间, 毫秒
+    pub time: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct WorkerList {
+    pub live: Vec<Worker>,
+    pub stale: Vec<Worker>,
+}
+
+/// 缓存目录占用, du 比较耗时, 放到线程里定时计算
+fn cache_usage() -> Option<u64> {
+    let mut usage = CACHE_USAGE.lock().unwrap();
+
+    let expired = match usage.0 {
+        Some(time) => time.elapsed() > CACHE_USAGE_INTERVAL,
+        None => true,
+    };
+
+    if expired {
+        usage.0 = Some(Instant::now());
+
+        thread::spawn(|| {
+            let output = Command::new("du")
+                .arg("-sk")
+                .arg(Config::cache_home())
+                .output();
+
+            let size = match output {
+                Ok(o) if o.status.success() => String::from_utf8_lossy(&o.stdout)
+                    .split_whitespace()
+                    .next()
+                    .and_then(|s| s.parse::<u64>().ok()),
+                Ok(o) => {
+                    info!("du error = {}", String::from_utf8_lossy(&o.stderr));
+                    None
+                }
+                Err(err) => {
+                    info!("du error = {}", err);
+                    None
+                }
+            };
+
+            if size.is_some() {
+                CACHE_USAGE.lock().unwrap().1 = size;
+            }
+        });
+    }
+
+    usage.1
+}
+
+impl Worker {
+    pub fn current() -> Self {
+        Worker {
+            name: Config::ip(),
+            addr: Config::addr(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            android_home: Config::android_home(),
+            cache_home: Config::cache_home(),
+            cache_usage: cache_usage(),
+            building: Config::is_building(),
+            build_id: Config::build_id(),
+            time: chrono::Utc::now().timestamp_millis(),
+        }
+    }
+
+    pub fn is_live(&self, now: i64) -> bool {
+        now - self.time < STALE_SECS * 1000
+    }
+
+    /// 登记本机打包服务状态
+    pub async fn heartbeat() {
+        let worker = Worker::current();
+
+        Redis::hset(
+            WORKERS,
+            &worker.name,
+            &serde_json::to_string(&worker).unwrap(),
+        )
+        .await;
+    }
+
+    pub async fn find(name: &str) -> Option<Worker> {
+        let value = Redis::hget(WORKERS, name).await?;
+
+        serde_json::from_str::<Worker>(&value).ok()
+    }
+
+    pub async fn list() -> WorkerList {
+        let now = chrono::Utc::now().timestamp_millis();
+        let mut list = WorkerList {
+            live: Vec::new(),
+            stale: Vec::new(),
+        };
+
+        for (name, value) in Redis::hgetall(WORKERS).await {
+            let worker = match serde_json::from_str::<Worker>(&value) {
+                Ok(w) => w,
+                Err(err) => {
+                    info!("worker {} error = {}", name, err);
+                    continue;
+                }
+            };
+
+            if worker.is_live(now) {
+                list.live.push(worker);
+            } else if now - worker.time > REMOVE_SECS * 1000 {
+                info!("remove worker {} ...", name);
+                Redis::hdel(WORKERS, &name).await;
+            } else {
+                list.stale.push(worker);
+            }
+        }
+
+        list.live.sort_by(|a, b| a.name.cmp(&b.name));
+        list.stale.sort_by_key(|w| Reverse(w.time));
+
+        list
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Worker;
+
+    #[test]
+    fn test_worker_live() {
+        let worker: Worker = serde_json::from_str(
+            r#"{
+                "name": "192.168.2.34-0.5.0",
+                "addr": "192.168.2.34:7002",
+                "version": "0.5.0",
+                "android_home": "/opt/android/sdk",
+                "cache_home": "/tmp/rust_build",
+                "building": false,
+                "time": 1612345678000
+            }"#,
+        )
+        .unwrap();
+
+        assert!(worker.is_live(1612345678000 + 10 * 1000));
+        assert!(!worker.is_live(1612345678000 + 90 * 1000));
+    }
+}